@@ -0,0 +1,816 @@
+/*   Z80 Disassembler
+     name is assemble.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::charcode::Charset;
+use crate::encode::{encode, has_shape, mnemonic_from_name};
+use crate::instruction::{Condition, Instruction, Mnemonic, Operand, Reg, RegPair};
+
+// **********************************************
+//      アセンブラ
+//
+//      この逆アセンブラのリスト / -a で出力したソース / 普通の Zilog の書き方を読める
+//      命令のバイト列は encode (Zilog のマニュアルのビットの並びから作った表) で作る
+//
+//      ラベル      NAME:   (1桁目から書く時は : を省略できる)  .NAME はローカルラベル
+//      疑似命令    ORG / DB DEFB DEFM / DW DEFW / DS DEFS / EQU / END
+//      数値        10 / 10D / 0AH / 0x0A / $0A / 1010B / %1010 / 12O / 'A' / $ (今のアドレス)
+//      演算子      + - * / % & | ^ ~ << >> と ( )
+// **********************************************
+
+const MAX_PASSES: usize = 8;        // ラベルの値が決まるまで、最大この回数アセンブルする
+
+// **********************************************
+//      アセンブル結果
+// **********************************************
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub origin: u16,                        // 先頭のアドレス
+    pub bytes: Vec<u8>,                     // origin からのバイナリ (ORG で飛んだところは 0)
+    pub symbols: BTreeMap<String, u16>,     // ラベルと EQU の値
+}
+
+// **********************************************
+//      エラー
+// **********************************************
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,        // 行番号 (1 から)
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// **********************************************
+//      ソースをアセンブルする
+// **********************************************
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut known: HashMap<String, i32> = HashMap::new();
+    for _ in 0..MAX_PASSES {
+        let pass = Pass::run(source, &known, false)?;
+        if pass.symbols == known {
+            break;
+        }
+        known = pass.symbols;
+    }
+    let pass = Pass::run(source, &known, true)?;
+    Ok(pass.program())
+}
+
+// オペランドを読んだところ  (数値の大きさや、C がレジスタか条件かは、命令の表で決める)
+#[derive(Clone, Copy, Debug)]
+enum Arg {
+    Op(Operand),                // 決まっているもの
+    RegC,                       // C レジスタか C 条件
+    Number(i32),                // 数値
+    Paren(i32),                 // (数値)  メモリかポート
+    Index(RegPair, i32),        // (IX+d)
+}
+
+// **********************************************
+//      １回分のアセンブル
+// **********************************************
+struct Pass<'a> {
+    known: &'a HashMap<String, i32>,    // 前の回で決まったシンボル
+    symbols: HashMap<String, i32>,      // この回で決まったシンボル
+    last: bool,                         // 最後の回 (未定義のシンボルはエラー)
+    address: u16,                       // 今のアドレス
+    line_address: u16,                  // 行の先頭のアドレス ($ の値)
+    global: String,                     // 直前のグローバルラベル (ローカルラベルの頭につける)
+    memory: BTreeMap<u16, u8>,          // 出力したバイト
+}
+
+impl<'a> Pass<'a> {
+    fn run(source: &str, known: &'a HashMap<String, i32>, last: bool) -> Result<Pass<'a>, AsmError> {
+        let mut pass = Pass{ known,
+                             symbols: HashMap::new(),
+                             last,
+                             address: 0,
+                             line_address: 0,
+                             global: String::new(),
+                             memory: BTreeMap::new(),
+                           };
+        for (i, text) in source.lines().enumerate() {
+            match pass.line(text) {
+                Ok(true)  => break,         // END
+                Ok(false) => {},
+                Err(message) => return Err(AsmError{ line: i + 1, message }),
+            }
+        }
+        Ok(pass)
+    }
+
+    // **********************************************
+    //      アセンブル結果にまとめる
+    // **********************************************
+    fn program(&self) -> Program {
+        let symbols = self.symbols.iter().map(|(n, v)| (n.clone(), *v as u16)).collect();
+        let (Some((&first, _)), Some((&last, _))) = (self.memory.first_key_value(), self.memory.last_key_value()) else {
+            return Program{ origin: 0, bytes: Vec::new(), symbols };
+        };
+        let mut bytes = vec![0; (last - first) as usize + 1];
+        for (address, byte) in &self.memory {
+            bytes[(address - first) as usize] = *byte;
+        }
+        Program{ origin: first, bytes, symbols }
+    }
+
+    // **********************************************
+    //      １行アセンブルする  END なら true
+    // **********************************************
+    fn line(&mut self, text: &str) -> Result<bool, String> {
+        let code = &text[..comment_start(text)];
+        let code = code.trim_end();
+        let code = code.strip_suffix('*').unwrap_or(code).trim_end();     // リストの未定義命令の印
+        self.line_address = self.address;
+
+        // ----- ラベル --------
+        let trimmed = code.trim_start();
+        let word_len = trimmed.find(|c: char| c.is_whitespace() || c == ':').unwrap_or(trimmed.len());
+        let (word, after) = trimmed.split_at(word_len);
+        let next = after.split_whitespace().next().unwrap_or_default();
+        let (label, rest) = if let Some(after) = after.strip_prefix(':') {
+            (Some(word), after)
+        } else if !word.is_empty() && (next.eq_ignore_ascii_case("EQU")
+                                       || (!code.starts_with(char::is_whitespace) && !is_keyword(word))) {
+            (Some(word), after)
+        } else {
+            (None, trimmed)
+        };
+
+        let rest = rest.trim();
+        let op_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (op, args) = rest.split_at(op_len);
+        let op = op.to_ascii_uppercase();
+        let args = args.trim();
+
+        if op == "EQU" {
+            let Some(label) = label else {
+                return Err(String::from("EQU needs a name"));
+            };
+            let value = self.eval(args)?;
+            self.define(label, value)?;
+            return Ok(false);
+        }
+        if let Some(label) = label {
+            self.define(label, self.address as i32)?;
+        }
+
+        match op.as_str() {
+            "" | "PROC" | "ENDP" | "LOCAL" | ".Z80" | "ASEG" => {},
+            "END" => return Ok(true),
+            "ORG" => self.address = self.eval(args)? as u16,
+            "DB" | "DEFB" | "DEFM" | "DM" => {
+                for item in split_args(args) {
+                    if let Some(text) = string_literal(&item) {
+                        for ch in text.chars() {
+                            let byte = char_code(ch).ok_or(format!("cannot write '{}' in DB", ch))?;
+                            self.emit(&[byte]);
+                        }
+                    } else {
+                        let value = self.eval(&item)?;
+                        self.check(value, -128, 255, &item)?;
+                        self.emit(&[value as u8]);
+                    }
+                }
+            },
+            "DW" | "DEFW" => {
+                for item in split_args(args) {
+                    let value = self.eval(&item)?;
+                    self.check(value, -32768, 65535, &item)?;
+                    self.emit(&(value as u16).to_le_bytes());
+                }
+            },
+            "DS" | "DEFS" => {
+                let items = split_args(args);
+                let (count, fill) = match &items[..] {
+                    [count]       => (self.eval(count)?, 0),
+                    [count, fill] => (self.eval(count)?, self.eval(fill)?),
+                    _ => return Err(String::from("DS needs a size")),
+                };
+                self.check(count, 0, 65536, &items[0])?;
+                self.emit(&vec![fill as u8; count.clamp(0, 65536) as usize]);
+            },
+            _ => {
+                let Some(mnemonic) = mnemonic_alias(&op) else {
+                    return Err(format!("unknown instruction: {}", op));
+                };
+                let bytes = self.instruction(mnemonic, args)?;
+                self.emit(&bytes);
+            },
+        }
+        Ok(false)
+    }
+
+    // **********************************************
+    //      シンボルを決める
+    // **********************************************
+    fn define(&mut self, name: &str, value: i32) -> Result<(), String> {
+        if !name.starts_with('.') {
+            self.global = name.to_string();
+        }
+        let name = self.qualify(name);
+        if self.symbols.insert(name.clone(), value).is_some() {
+            return Err(format!("duplicate symbol: {}", name));
+        }
+        Ok(())
+    }
+
+    // ローカルラベル (.NAME) は、直前のグローバルラベルの名前をつける
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.global, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<i32> {
+        let name = self.qualify(name);
+        self.symbols.get(&name).or_else(|| self.known.get(&name)).copied()
+    }
+
+    // バイト列を出力する
+    fn emit(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.memory.insert(self.address, *byte);
+            self.address = self.address.wrapping_add(1);
+        }
+    }
+
+    // 値の範囲を確かめる (最後の回だけ)
+    fn check(&self, value: i32, min: i32, max: i32, text: &str) -> Result<(), String> {
+        if self.last && !(min..=max).contains(&value) {
+            return Err(format!("value out of range: {}", text));
+        }
+        Ok(())
+    }
+
+    // **********************************************
+    //      式の値
+    // **********************************************
+    fn eval(&self, text: &str) -> Result<i32, String> {
+        let mut expr = Expr{ chars: text.chars().collect(), pos: 0, pass: self };
+        let value = expr.or()?;
+        expr.skip_space();
+        if expr.pos < expr.chars.len() {
+            return Err(format!("bad expression: {}", text));
+        }
+        Ok(value)
+    }
+
+    // **********************************************
+    //      命令をバイト列にする
+    // **********************************************
+    fn instruction(&self, mnemonic: Mnemonic, args: &str) -> Result<Vec<u8>, String> {
+        let mut parsed = Vec::new();
+        for item in split_args(args) {
+            parsed.push(self.parse_arg(&item)?);
+        }
+        // ----- 別の書き方を、逆アセンブラの書き方にそろえる --------
+        match (mnemonic, &parsed[..]) {
+            (Mnemonic::Ex, [Arg::Op(Operand::RegPair(RegPair::AF)), Arg::Op(Operand::RegPair(RegPair::AF))])
+                => parsed[1] = Arg::Op(Operand::RegPair(RegPair::AFAlt)),                 // EX AF,AF
+            (Mnemonic::Sub | Mnemonic::And | Mnemonic::Xor | Mnemonic::Or | Mnemonic::Cp, [Arg::Op(Operand::Reg(Reg::A)), _])
+                => { parsed.remove(0); },                                                // SUB A,B
+            (Mnemonic::Add | Mnemonic::Adc | Mnemonic::Sbc, [Arg::Op(Operand::Reg(_) | Operand::Indirect(_)) | Arg::RegC | Arg::Number(_) | Arg::Index(..)])
+                => parsed.insert(0, Arg::Op(Operand::Reg(Reg::A))),                      // ADD B
+            _ => {},
+        }
+
+        // ----- 命令の表にある組み合わせを探す --------
+        let choices: Vec<Vec<Operand>> = parsed.iter().enumerate()
+                                               .map(|(i, arg)| self.choices(mnemonic, i, *arg))
+                                               .collect();
+        let mut operands = Vec::new();
+        if !find_shape(mnemonic, &choices, &mut operands) {
+            return Err(format!("invalid operands: {} {}", mnemonic, args));
+        }
+        for (arg, operand) in parsed.iter().zip(&mut operands) {
+            match (arg, operand) {
+                (_, Operand::Address(a)) if !self.last          => *a = self.line_address,     // JR の飛び先が決まっていない時
+                (Arg::Number(v), Operand::Imm8(_))              => self.check(*v, -128, 255, args)?,
+                (Arg::Number(v), Operand::Imm16(_) | Operand::Address(_)) => self.check(*v, -32768, 65535, args)?,
+                (Arg::Number(v), Operand::Bit(_))               => self.check(*v, 0, 7, args)?,
+                (Arg::Number(v), Operand::InterruptMode(_))     => self.check(*v, 0, 2, args)?,
+                (Arg::Number(v), Operand::Restart(_)) if self.last && (*v & !0x38) != 0
+                    => return Err(format!("bad RST address: {}", args)),
+                (Arg::Paren(v), Operand::Port(_))               => self.check(*v, 0, 255, args)?,
+                (Arg::Index(_, d), _)                           => self.check(*d, -128, 127, args)?,
+                _ => {},
+            }
+        }
+        let inst = Instruction{ address: self.address, bytes: Vec::new(), mnemonic, operands, undocumented: false };
+        encode(&inst)
+    }
+
+    // **********************************************
+    //      オペランドを１つ読む
+    // **********************************************
+    fn parse_arg(&self, text: &str) -> Result<Arg, String> {
+        let up = text.to_ascii_uppercase();
+        if let Some(operand) = register(&up) {
+            return Ok(Arg::Op(operand));
+        }
+        if up == "C" {
+            return Ok(Arg::RegC);
+        }
+        if let Some(c) = condition(&up) {
+            return Ok(Arg::Op(Operand::Condition(c)));
+        }
+        if let Some(inner) = up.strip_prefix('(').and_then(|s| s.strip_suffix(')')).filter(|_| is_enclosed(&up)) {
+            let inner = inner.trim();
+            match inner {
+                "BC" => return Ok(Arg::Op(Operand::Indirect(RegPair::BC))),
+                "DE" => return Ok(Arg::Op(Operand::Indirect(RegPair::DE))),
+                "HL" => return Ok(Arg::Op(Operand::Indirect(RegPair::HL))),
+                "SP" => return Ok(Arg::Op(Operand::Indirect(RegPair::SP))),
+                "IX" => return Ok(Arg::Op(Operand::Indirect(RegPair::IX))),
+                "IY" => return Ok(Arg::Op(Operand::Indirect(RegPair::IY))),
+                "C"  => return Ok(Arg::Op(Operand::PortC)),
+                _ => {},
+            }
+            for (name, pair) in [("IX", RegPair::IX), ("IY", RegPair::IY)] {
+                if let Some(d) = inner.strip_prefix(name).map(str::trim_start).filter(|d| d.starts_with(['+', '-'])) {
+                    return Ok(Arg::Index(pair, self.eval(d)?));
+                }
+            }
+            return Ok(Arg::Paren(self.eval(&text.trim()[1..text.trim().len() - 1])?));
+        }
+        Ok(Arg::Number(self.eval(text)?))
+    }
+
+    // **********************************************
+    //      オペランドが何になりうるか (命令の表で決める)
+    // **********************************************
+    fn choices(&self, mnemonic: Mnemonic, index: usize, arg: Arg) -> Vec<Operand> {
+        match arg {
+            Arg::Op(Operand::Indirect(p @ (RegPair::IX | RegPair::IY)))
+                => vec![Operand::Indirect(p), Operand::Indexed(p, 0)],     // JP (IX) と LD A,(IX)
+            Arg::Op(operand) => vec![operand],
+            Arg::RegC => vec![Operand::Reg(Reg::C), Operand::Condition(Condition::C)],
+            Arg::Number(v) => match mnemonic {
+                Mnemonic::Jp | Mnemonic::Jr | Mnemonic::Call | Mnemonic::Djnz => vec![Operand::Address(v as u16)],
+                Mnemonic::Rst => vec![Operand::Restart(v as u8)],
+                Mnemonic::Im  => vec![Operand::InterruptMode(v as u8)],
+                Mnemonic::Bit | Mnemonic::Set | Mnemonic::Res if index == 0 => vec![Operand::Bit(v as u8)],
+                _ => vec![Operand::Imm8(v as u8), Operand::Imm16(v as u16)],
+            },
+            Arg::Paren(v) => vec![Operand::Memory(v as u16), Operand::Port(v as u8)],
+            Arg::Index(p, d) => vec![Operand::Indexed(p, d as i8)],
+        }
+    }
+}
+
+// **********************************************
+//      命令の表にあるオペランドの組み合わせを探す
+// **********************************************
+fn find_shape(mnemonic: Mnemonic, choices: &[Vec<Operand>], operands: &mut Vec<Operand>) -> bool {
+    let Some((first, rest)) = choices.split_first() else {
+        return has_shape(mnemonic, operands);
+    };
+    for operand in first {
+        operands.push(*operand);
+        if find_shape(mnemonic, rest, operands) {
+            return true;
+        }
+        operands.pop();
+    }
+    false
+}
+
+// **********************************************
+//      式  (優先順位の低い順)
+// **********************************************
+struct Expr<'a, 'b> {
+    chars: Vec<char>,
+    pos: usize,
+    pass: &'b Pass<'a>,
+}
+
+impl Expr<'_, '_> {
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    // 演算子があれば読み飛ばす
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_space();
+        let len = op.chars().count();
+        if self.chars.len() >= self.pos + len && self.chars[self.pos..self.pos + len].iter().copied().eq(op.chars()) {
+            self.pos += len;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<i32, String> {
+        let mut value = self.xor()?;
+        while self.eat("|") {
+            value |= self.xor()?;
+        }
+        Ok(value)
+    }
+
+    fn xor(&mut self) -> Result<i32, String> {
+        let mut value = self.and()?;
+        while self.eat("^") {
+            value ^= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i32, String> {
+        let mut value = self.shift()?;
+        while self.eat("&") {
+            value &= self.shift()?;
+        }
+        Ok(value)
+    }
+
+    fn shift(&mut self) -> Result<i32, String> {
+        let mut value = self.add()?;
+        loop {
+            if self.eat("<<") {
+                value = value.wrapping_shl(self.add()? as u32);
+            } else if self.eat(">>") {
+                value = value.wrapping_shr(self.add()? as u32);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn add(&mut self) -> Result<i32, String> {
+        let mut value = self.mul()?;
+        loop {
+            if self.eat("+") {
+                value = value.wrapping_add(self.mul()?);
+            } else if self.eat("-") {
+                value = value.wrapping_sub(self.mul()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn mul(&mut self) -> Result<i32, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat("*") {
+                value = value.wrapping_mul(self.unary()?);
+            } else if self.eat("/") || self.eat("%") {
+                let op = self.chars[self.pos - 1];
+                let rhs = self.unary()?;
+                if rhs == 0 {
+                    return Err(String::from("division by zero"));
+                }
+                value = if op == '/' { value.wrapping_div(rhs) } else { value.wrapping_rem(rhs) };
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i32, String> {
+        if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.eat("~") {
+            Ok(!self.unary()?)
+        } else {
+            self.primary()
+        }
+    }
+
+    // **********************************************
+    //      数値 / 文字 / シンボル / ( 式 )
+    // **********************************************
+    fn primary(&mut self) -> Result<i32, String> {
+        self.skip_space();
+        let Some(&c) = self.chars.get(self.pos) else {
+            return Err(String::from("missing value"));
+        };
+        if c == '(' {
+            self.pos += 1;
+            let value = self.or()?;
+            if !self.eat(")") {
+                return Err(String::from("missing )"));
+            }
+            return Ok(value);
+        }
+        if c == '\'' || c == '"' {          // 'A'
+            return match (self.chars.get(self.pos + 1), self.chars.get(self.pos + 2)) {
+                (Some(&ch), Some(&close)) if close == c => {
+                    self.pos += 3;
+                    char_code(ch).map(i32::from).ok_or(format!("bad character: {}", ch))
+                },
+                _ => Err(String::from("bad character constant")),
+            };
+        }
+        let start = self.pos;
+        if c == '$' || c == '%' {           // $ / $0A / %1010
+            self.pos += 1;
+            let digits = self.word();
+            if digits.is_empty() {
+                return if c == '$' { Ok(self.pass.line_address as i32) } else { Err(String::from("bad number")) };
+            }
+            let radix = if c == '$' { 16 } else { 2 };
+            return i32::from_str_radix(&digits, radix).map_err(|_| format!("bad number: {}{}", c, digits));
+        }
+        let word = self.word();
+        if word.is_empty() {
+            return Err(format!("bad expression: {}", self.chars[start..].iter().collect::<String>()));
+        }
+        if c.is_ascii_digit() {
+            return number(&word).ok_or(format!("bad number: {}", word));
+        }
+        match self.pass.lookup(&word) {
+            Some(value) => Ok(value),
+            None if self.pass.last => Err(format!("undefined symbol: {}", word)),
+            None => Ok(0),          // 後で決まる
+        }
+    }
+
+    // 英数字の並び
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_alphanumeric() || "_.?@".contains(*c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+// **********************************************
+//      数値  10 / 10D / 0AH / 0x0A / 1010B / 12O
+// **********************************************
+fn number(word: &str) -> Option<i32> {
+    let up = word.to_ascii_uppercase();
+    if let Some(hex) = up.strip_prefix("0X") {
+        return i32::from_str_radix(hex, 16).ok();
+    }
+    let (digits, radix) = match up.chars().last() {
+        Some('H')       => (&up[..up.len() - 1], 16),
+        Some('B')       => (&up[..up.len() - 1], 2),
+        Some('D')       => (&up[..up.len() - 1], 10),
+        Some('O' | 'Q') => (&up[..up.len() - 1], 8),
+        _               => (&up[..], 10),
+    };
+    i32::from_str_radix(digits, radix).ok()
+}
+
+// 文字コード  アスキーと、PC-6001 のひらがな・カタカナ
+fn char_code(ch: char) -> Option<u8> {
+    if ch.is_ascii() && !ch.is_ascii_control() {
+        return Some(ch as u8);
+    }
+    let mut buf = [0; 4];
+    let s: &str = ch.encode_utf8(&mut buf);
+    (0x80..=0xff).find(|code| Charset::Pc6001.to_text(*code) == Some(s))
+}
+
+fn register(name: &str) -> Option<Operand> {
+    let operand = match name {
+        "A"   => Operand::Reg(Reg::A),
+        "B"   => Operand::Reg(Reg::B),
+        "D"   => Operand::Reg(Reg::D),
+        "E"   => Operand::Reg(Reg::E),
+        "H"   => Operand::Reg(Reg::H),
+        "L"   => Operand::Reg(Reg::L),
+        "F"   => Operand::Reg(Reg::F),
+        "I"   => Operand::Reg(Reg::I),
+        "R"   => Operand::Reg(Reg::R),
+        "IXH" => Operand::Reg(Reg::IXH),
+        "IXL" => Operand::Reg(Reg::IXL),
+        "IYH" => Operand::Reg(Reg::IYH),
+        "IYL" => Operand::Reg(Reg::IYL),
+        "BC"  => Operand::RegPair(RegPair::BC),
+        "DE"  => Operand::RegPair(RegPair::DE),
+        "HL"  => Operand::RegPair(RegPair::HL),
+        "SP"  => Operand::RegPair(RegPair::SP),
+        "AF"  => Operand::RegPair(RegPair::AF),
+        "AF'" => Operand::RegPair(RegPair::AFAlt),
+        "IX"  => Operand::RegPair(RegPair::IX),
+        "IY"  => Operand::RegPair(RegPair::IY),
+        _ => return None,
+    };
+    Some(operand)
+}
+
+fn condition(name: &str) -> Option<Condition> {
+    match name {
+        "NZ" => Some(Condition::NZ),
+        "Z"  => Some(Condition::Z),
+        "NC" => Some(Condition::NC),
+        "PO" => Some(Condition::PO),
+        "PE" => Some(Condition::PE),
+        "P"  => Some(Condition::P),
+        "M"  => Some(Condition::M),
+        _    => None,           // C はレジスタかもしれないので、ここでは決めない
+    }
+}
+
+// ニーモニック  SLL は SLI / SL1 とも書く
+fn mnemonic_alias(name: &str) -> Option<Mnemonic> {
+    match name {
+        "SLI" | "SL1" => Some(Mnemonic::Sll),
+        _             => mnemonic_from_name(name),
+    }
+}
+
+// 1桁目に書いてあってもラベルではないもの
+fn is_keyword(word: &str) -> bool {
+    let up = word.to_ascii_uppercase();
+    mnemonic_alias(&up).is_some()
+        || matches!(up.as_str(), "ORG" | "DB" | "DEFB" | "DEFM" | "DM" | "DW" | "DEFW" | "DS" | "DEFS"
+                               | "EQU" | "END" | "PROC" | "ENDP" | "LOCAL" | ".Z80" | "ASEG")
+}
+
+// **********************************************
+//      文字列の始まりか？  AF' の ' は文字列ではない
+// **********************************************
+fn is_quote(chars: &[char], i: usize) -> bool {
+    match chars[i] {
+        '"'  => true,
+        '\'' => !(i >= 2 && chars[i - 2].eq_ignore_ascii_case(&'A') && chars[i - 1].eq_ignore_ascii_case(&'F')
+                  && (i == 2 || !chars[i - 3].is_alphanumeric())),
+        _    => false,
+    }
+}
+
+// コメント (;) の位置  文字列の中の ; は除く
+fn comment_start(text: &str) -> usize {
+    let chars: Vec<char> = text.chars().collect();
+    let mut quote: Option<char> = None;
+    let mut offset = 0;
+    for (i, c) in chars.iter().enumerate() {
+        match quote {
+            Some(q) if *c == q => quote = None,
+            Some(_) => {},
+            None if *c == ';' => return offset,
+            None if is_quote(&chars, i) => quote = Some(*c),
+            None => {},
+        }
+        offset += c.len_utf8();
+    }
+    text.len()
+}
+
+// **********************************************
+//      オペランドを , で分ける  ( ) や文字列の中の , は除く
+// **********************************************
+fn split_args(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    for (i, c) in chars.iter().enumerate() {
+        match quote {
+            Some(q) if *c == q => quote = None,
+            Some(_) => {},
+            None if is_quote(&chars, i) => quote = Some(*c),
+            None if *c == '(' => depth += 1,
+            None if *c == ')' => depth -= 1,
+            None if *c == ',' && depth == 0 => {
+                items.push(item.trim().to_string());
+                item.clear();
+                continue;
+            },
+            None => {},
+        }
+        item.push(*c);
+    }
+    if !item.trim().is_empty() || !items.is_empty() {
+        items.push(item.trim().to_string());
+    }
+    items
+}
+
+// "..." / '...' の中身  ('A' のような１文字も文字列として扱う)
+fn string_literal(item: &str) -> Option<&str> {
+    let quote = item.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    item.strip_prefix(quote)?.strip_suffix(quote).filter(|s| !s.contains(quote))
+}
+
+// 全体が１つの ( ) でくくられているか？  (1)+(2) は違う
+fn is_enclosed(text: &str) -> bool {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 && i + 1 < text.len() {
+                    return false;
+                }
+            },
+            _ => {},
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap_or_else(|e| panic!("{}", e)).bytes
+    }
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn numbers_and_expressions() {
+        let program = assemble("        ORG 8000H\n\
+                                START:  LD HL,DATA\n\
+                                        JR START\n\
+                                DATA:   DB 'AB',0,10,0AH,0x0A,$0A,1010B,%1010,12O\n\
+                                        DW DATA,$\n\
+                                N       EQU 3*(2+1)-1\n\
+                                        DS N,0FFH\n\
+                                        LD A,N<<1|1\n\
+                                        END").unwrap();
+        assert_eq!(program.origin, 0x8000);
+        assert_eq!(program.bytes, [0x21, 0x05, 0x80, 0x18, 0xfb,
+                                   0x41, 0x42, 0x00, 0x0a, 0x0a, 0x0a, 0x0a, 0x0a, 0x0a, 0x0a,
+                                   0x05, 0x80, 0x0f, 0x80,
+                                   0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                   0x3e, 0x11]);
+        assert_eq!(program.symbols["N"], 8);
+        assert_eq!(program.symbols["DATA"], 0x8005);
+    }
+
+    // .NAME は直前のラベルごとに別のもの
+    #[test]
+    fn local_labels() {
+        let program = assemble("        ORG 100H\nMAIN:   NOP\n.L1:    DJNZ .L1\nSUB:    NOP\n.L1:    JR .L1").unwrap();
+        assert_eq!(program.bytes, [0x00, 0x10, 0xfe, 0x00, 0x18, 0xfe]);
+        assert_eq!(program.symbols["MAIN.L1"], 0x101);
+        assert_eq!(program.symbols["SUB.L1"], 0x104);
+    }
+
+    // プリフィクスのつく命令と未定義命令
+    #[test]
+    fn prefixed_instructions() {
+        assert_eq!(bytes("        LD A,(IX+5)\n        SUB (IY-5)\n        RLC (IY+5),B\n        BIT 7,(HL)\n        LD (1234H),BC"),
+                   [0xdd, 0x7e, 0x05, 0xfd, 0x96, 0xfb, 0xfd, 0xcb, 0x05, 0x00, 0xcb, 0x7e, 0xed, 0x43, 0x34, 0x12]);
+        assert_eq!(bytes("        JP (HL)\n        EX AF,AF'\n        OUT (C),0\n        IN F,(C)\n        SLL B\n        LD IXH,A"),
+                   [0xe9, 0x08, 0xed, 0x71, 0xed, 0x70, 0xcb, 0x30, 0xdd, 0x67]);
+    }
+
+    // ORG で飛んだところは 0 で埋める
+    #[test]
+    fn org_gap() {
+        assert_eq!(bytes("        ORG 10H\n        NOP\n        ORG 13H\n        RET"), [0x00, 0x00, 0x00, 0xc9]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("        ORG 0\n        NOP\n        JR FAR\n        DS 200\nFAR:    NOP"),
+                   AsmError { line: 3, message: "relative jump out of range: JR    00CBH".to_string() });
+        assert_eq!(error("        NOP\n        LD A,UNDEF"),
+                   AsmError { line: 2, message: "undefined symbol: UNDEF".to_string() });
+        assert_eq!(error("        LD Q,1").line, 1);
+    }
+}
@@ -272,3 +272,52 @@ pub const TOUTF8: [&str; 0x100]= [
     "　",
 
 ];
+
+// **********************************************
+//      文字列 (DB "...") に使う文字セット
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Charset {
+    Ascii,          // 20H-7EH だけ
+    #[default]
+    Pc6001,         // 20H-7EH と、TOUTF8 のひらがな・カタカナ
+    Sharp,          // Sharp MZ  20H-5DH と、80H からにある小文字
+}
+
+impl Charset {
+    // **********************************************
+    //      文字列の中に書ける文字に変換する
+    //      書けない時は None
+    // **********************************************
+    pub fn to_text(self, code: u8) -> Option<&'static str> {
+        match code {
+            _ if self == Charset::Sharp => {let i = (sharp_to_ascii(code)? - 0x20) as usize;
+                                            Some(&ASCII[i..=i])},
+            0x20..=0x7e => {let i = (code - 0x20) as usize;
+                            Some(&ASCII[i..=i])},
+            0x86..=0x8f | 0x91..=0x9f | 0xa1..=0xdf | 0xe0..=0xfd if self == Charset::Pc6001
+                => Some(TOUTF8[code as usize]),
+            _ => None,
+        }
+    }
+}
+
+// 20H-7EH の文字
+const ASCII: &str = " !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+
+// **********************************************
+//      Sharp MZ の文字コードを ASCII にする
+//      20H-5DH は ASCII と同じ  小文字は 80H-BFH にばらばらに置かれている
+// **********************************************
+fn sharp_to_ascii(code: u8) -> Option<u8> {
+    let ascii = match code {
+        0x20..=0x5d => code,
+        0x92 => b'e', 0x96 => b't', 0x97 => b'g', 0x98 => b'h', 0x9a => b'b', 0x9b => b'x',
+        0x9c => b'd', 0x9d => b'r', 0x9e => b'p', 0x9f => b'c', 0xa0 => b'q', 0xa1 => b'a',
+        0xa2 => b'z', 0xa3 => b'w', 0xa4 => b's', 0xa5 => b'u', 0xa6 => b'y', 0xa9 => b'k',
+        0xaa => b'f', 0xab => b'v', 0xaf => b'j', 0xb0 => b'n', 0xb3 => b'm', 0xb7 => b'o',
+        0xb8 => b'l', 0xbd => b'i',
+        _ => return None,
+    };
+    Some(ascii)
+}
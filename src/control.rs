@@ -0,0 +1,254 @@
+/*   Z80 Disassembler
+     name is control.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+// **********************************************
+//      コントロールファイル
+//
+//      １行に１つ、種類とアドレス(範囲)を書く
+//          entry    4100           エントリーポイント
+//          code     4100-41FF      命令
+//          bytes    4000-40FF      バイトデータ (DB)
+//          words    4000-40FF      ワードデータ (DW)
+//          pointers 4000-40FF      アドレスの表 (DW ラベル)
+//          text     5000-53FF      文字列
+//          skip     6000-60FF      出力しない
+//          label    4100 START     ラベル名をつける
+//      範囲のうしろに名前を書くと、先頭にラベルがつく
+//      ; か # から後ろはコメント
+//      アドレスは 4100 / 4100H / 0x4100 / $4100 のどれでもよい
+// **********************************************
+
+// 範囲の種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RegionKind {
+    Code, Bytes, Words, Pointers, Text, Skip,
+}
+
+// 範囲  end も含む
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub kind: RegionKind,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Control {
+    pub regions: Vec<Region>,           // 範囲  後に書いたものが優先
+    pub entries: Vec<u16>,              // エントリーポイント
+    pub labels: Vec<(u16, String)>,     // 名前つきのラベル
+}
+
+// コントロールファイルのエラー
+#[derive(Debug)]
+pub struct ControlError {
+    pub line: usize,        // 行番号 (1から)
+    pub message: String,
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+// **********************************************
+//      アドレスを読む  4100 / 4100H / 0x4100 / $4100
+// **********************************************
+pub fn parse_address(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let hex = if let Some(h) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        h
+    } else if let Some(h) = s.strip_prefix('$') {
+        h
+    } else if let Some(h) = s.strip_suffix('H').or_else(|| s.strip_suffix('h')) {
+        h
+    } else {
+        s
+    };
+    u16::from_str_radix(hex, 16).ok()
+}
+
+impl Control {
+    // **********************************************
+    //      ファイルから読み込む
+    // **********************************************
+    pub fn load(filename: &str) -> io::Result<Control> {
+        let text = fs::read_to_string(filename)?;
+        Control::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    // **********************************************
+    //      文字列を読み込む
+    // **********************************************
+    pub fn parse(text: &str) -> Result<Control, ControlError> {
+        let mut control = Control::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ControlError { line: i + 1, message };
+
+            let line = line.split([';', '#']).next().unwrap_or("");  // コメントを取る
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let keyword = words[0].to_lowercase();
+            let Some(range) = words.get(1) else {
+                return Err(error(format!("address is missing after '{}'", words[0])));
+            };
+            let name = words.get(2).map(|s| s.to_string());
+            if words.len() > 3 {
+                return Err(error(format!("unexpected '{}'", words[3])));
+            }
+
+            // ----- 範囲を読む  4000-40FF か 4000 -----
+            let (start, end) = match range.split_once('-') {
+                Some((s, e)) => (parse_address(s), parse_address(e)),
+                None         => (parse_address(range), None),
+            };
+            let Some(start) = start else {
+                return Err(error(format!("invalid address '{}'", range)));
+            };
+            if range.contains('-') && end.is_none() {
+                return Err(error(format!("invalid address '{}'", range)));
+            }
+
+            let kind = match keyword.as_str() {
+                "entry" => {
+                    control.entries.push(start);
+                    if let Some(name) = name {
+                        control.labels.push((start, name));
+                    }
+                    continue;
+                },
+                "label" => {
+                    let Some(name) = name else {
+                        return Err(error(String::from("label name is missing")));
+                    };
+                    control.labels.push((start, name));
+                    continue;
+                },
+                "code"     => RegionKind::Code,
+                "bytes"    => RegionKind::Bytes,
+                "words"    => RegionKind::Words,
+                "pointers" => RegionKind::Pointers,
+                "text"     => RegionKind::Text,
+                "skip"     => RegionKind::Skip,
+                _ => return Err(error(format!("unknown type '{}'", words[0]))),
+            };
+            // 範囲を省略した時は１つ分
+            let end = end.unwrap_or(match kind {
+                RegionKind::Words | RegionKind::Pointers => start.wrapping_add(1),
+                _ => start,
+            });
+            if end < start {
+                return Err(error(format!("end address is before start address '{}'", range)));
+            }
+            control.regions.push(Region { start, end, kind });
+            if let Some(name) = name {
+                control.labels.push((start, name));
+            }
+        }
+        Ok(control)
+    }
+
+    // **********************************************
+    //      別の指定を後ろに足す (範囲は足したものが優先)
+    // **********************************************
+    pub fn extend(&mut self, other: Control) {
+        self.regions.extend(other.regions);
+        self.entries.extend(other.entries);
+        self.labels.extend(other.labels);
+    }
+
+    // **********************************************
+    //      アドレスの範囲の種類  指定がなければ None
+    // **********************************************
+    pub fn kind_of(&self, address: u16) -> Option<RegionKind> {
+        self.regions.iter().rev()
+            .find(|r| r.start <= address && address <= r.end)
+            .map(|r| r.kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        Control::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn addresses() {
+        for s in ["4100", "4100H", "4100h", "0x4100", "$4100", " 4100 "] {
+            assert_eq!(parse_address(s), Some(0x4100), "{}", s);
+        }
+        assert_eq!(parse_address("G000"), None);
+        assert_eq!(parse_address("10000"), None);
+    }
+
+    #[test]
+    fn parse() {
+        let control = Control::parse("; PC-6001 のゲーム\n\
+                                      entry    4100 START\n\
+                                      text     5000-53FF MESSAGE  # 文字列\n\
+                                      pointers 4010\n\
+                                      label    4200 LOOP\n\
+                                      CODE     5100-51FF\n").unwrap();
+        assert_eq!(control.entries, [0x4100]);
+        assert_eq!(control.labels, [(0x4100, String::from("START")), (0x5000, String::from("MESSAGE")),
+                                    (0x4200, String::from("LOOP"))]);
+        assert_eq!(control.regions, [Region{ start: 0x5000, end: 0x53ff, kind: RegionKind::Text },
+                                     Region{ start: 0x4010, end: 0x4011, kind: RegionKind::Pointers },
+                                     Region{ start: 0x5100, end: 0x51ff, kind: RegionKind::Code }]);
+    }
+
+    // 後に書いた範囲が優先
+    #[test]
+    fn later_region_wins() {
+        let control = Control::parse("bytes 4000-40FF\nskip 4080-408F\n").unwrap();
+        assert_eq!(control.kind_of(0x4000), Some(RegionKind::Bytes));
+        assert_eq!(control.kind_of(0x4080), Some(RegionKind::Skip));
+        assert_eq!(control.kind_of(0x4100), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("entry"), "line 1: address is missing after 'entry'");
+        assert_eq!(error("\nbytes 4000-40FF A B"), "line 2: unexpected 'B'");
+        assert_eq!(error("bytes 40G0"), "line 1: invalid address '40G0'");
+        assert_eq!(error("bytes 4000-"), "line 1: invalid address '4000-'");
+        assert_eq!(error("label 4000"), "line 1: label name is missing");
+        assert_eq!(error("data 4000"), "line 1: unknown type 'data'");
+        assert_eq!(error("bytes 40FF-4000"), "line 1: end address is before start address '40FF-4000'");
+    }
+}
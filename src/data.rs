@@ -0,0 +1,152 @@
+/*   Z80 Disassembler
+     name is data.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+use crate::line::{DataKind, Line};
+
+// **********************************************
+//      データの出力方法
+// **********************************************
+#[derive(Clone, Debug)]
+pub struct DataOptions {
+    pub bytes_per_line: usize,  // DB / DW の１行のバイト数
+    pub fill_min: usize,        // 同じバイトがこれだけ続いたら DS にする (0 なら DS にしない)
+    pub charset: Charset,       // 文字列の文字セット
+}
+
+impl Default for DataOptions {
+    fn default() -> Self {
+        DataOptions{ bytes_per_line: 8, fill_min: 16, charset: Charset::default() }
+    }
+}
+
+// **********************************************
+//      データを行に分ける
+//      address は bytes の先頭のアドレス
+// **********************************************
+pub fn data_lines(address: u16, bytes: &[u8], kind: DataKind, options: &DataOptions) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let (size, kind) = match kind {
+            DataKind::Bytes | DataKind::Fill => {
+                let run = rest.iter().take_while(|b| **b == rest[0]).count();
+                if options.fill_min > 0 && run >= options.fill_min {
+                    (run, DataKind::Fill)
+                } else {
+                    // 次の DS の手前まで
+                    let mut size = 1;
+                    while size < rest.len().min(options.bytes_per_line.max(1)) {
+                        let run = rest[size..].iter().take_while(|b| **b == rest[size]).count();
+                        if options.fill_min > 0 && run >= options.fill_min {
+                            break;
+                        }
+                        size += 1;
+                    }
+                    (size, DataKind::Bytes)
+                }
+            },
+            DataKind::Words | DataKind::Pointers => {
+                if rest.len() == 1 {
+                    (1, DataKind::Bytes)    // ワードにならない最後の１バイト
+                } else if kind == DataKind::Pointers {
+                    (2, kind)               // アドレスの表は１行に１つ
+                } else {
+                    (rest.len().min(options.bytes_per_line.max(2)) & !1, kind)
+                }
+            },
+            DataKind::Text => {
+                // 00H のうしろか、bytes_per_line の４倍で行を分ける
+                let max = rest.len().min(options.bytes_per_line.max(1) * 4);
+                let size = rest[..max].iter().position(|b| *b == 0).map_or(max, |p| p + 1);
+                (size, kind)
+            },
+        };
+        lines.push(Line::Data { address: address.wrapping_add(offset as u16),
+                                bytes: rest[..size].to_vec(),
+                                kind });
+        offset += size;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 行ごとの (アドレス, バイト数, 種類)
+    fn split(bytes: &[u8], kind: DataKind, options: &DataOptions) -> Vec<(u16, usize, DataKind)> {
+        data_lines(0x4000, bytes, kind, options).iter().map(|line| match line {
+            Line::Data { address, bytes, kind } => (*address, bytes.len(), *kind),
+            other => panic!("{:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn bytes_per_line() {
+        let options = DataOptions::default();
+        let bytes: Vec<u8> = (0..20).collect();
+        assert_eq!(split(&bytes, DataKind::Bytes, &options),
+                   [(0x4000, 8, DataKind::Bytes), (0x4008, 8, DataKind::Bytes), (0x4010, 4, DataKind::Bytes)]);
+    }
+
+    #[test]
+    fn fill() {
+        let options = DataOptions::default();
+        // 3 バイトのあと 00H が 16 バイト、そのあと 1 バイト
+        let mut bytes = vec![1, 2, 3];
+        bytes.extend([0; 16]);
+        bytes.push(4);
+        assert_eq!(split(&bytes, DataKind::Bytes, &options),
+                   [(0x4000, 3, DataKind::Bytes), (0x4003, 16, DataKind::Fill), (0x4013, 1, DataKind::Bytes)]);
+
+        // 15 バイトでは DS にしない
+        assert_eq!(split(&[0; 15], DataKind::Bytes, &options),
+                   [(0x4000, 8, DataKind::Bytes), (0x4008, 7, DataKind::Bytes)]);
+
+        // fill_min が 0 なら DS にしない
+        let options = DataOptions { fill_min: 0, ..DataOptions::default() };
+        assert_eq!(split(&[0; 16], DataKind::Fill, &options),
+                   [(0x4000, 8, DataKind::Bytes), (0x4008, 8, DataKind::Bytes)]);
+    }
+
+    #[test]
+    fn words() {
+        let options = DataOptions::default();
+        assert_eq!(split(&[0; 11], DataKind::Words, &options),
+                   [(0x4000, 8, DataKind::Words), (0x4008, 2, DataKind::Words), (0x400a, 1, DataKind::Bytes)]);
+        assert_eq!(split(&[0; 5], DataKind::Pointers, &options),
+                   [(0x4000, 2, DataKind::Pointers), (0x4002, 2, DataKind::Pointers), (0x4004, 1, DataKind::Bytes)]);
+    }
+
+    #[test]
+    fn text() {
+        let options = DataOptions { bytes_per_line: 2, ..DataOptions::default() };
+        assert_eq!(split(b"AB\0CDEFGHIJ", DataKind::Text, &options),
+                   [(0x4000, 3, DataKind::Text), (0x4003, 8, DataKind::Text)]);
+    }
+}
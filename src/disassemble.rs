@@ -0,0 +1,853 @@
+/*   Z80 Disassembler
+     name is disassemble.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::io;
+use std::io::prelude::*;
+
+use crate::charcode::TOUTF8;  // アスキーコードを文字に変換するテーブル
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::control::{Control, RegionKind};
+use crate::data::{data_lines, DataOptions};
+use crate::flow;
+use crate::format::{format_data, format_instruction, format_word, Syntax};
+use crate::instruction::{Condition, Instruction, Mnemonic, Operand, Reg, RegPair};
+use crate::instruction::Mnemonic::*;
+use crate::label::Labels;
+use crate::loader::{self, Image, LoadError, LoadOptions};
+use crate::line::{DataKind, Line};
+use crate::source::{write_source, Dialect};
+use crate::system::System;
+use crate::verify::{self, Difference};
+
+// 命令にしてよい範囲か？ (指定なしか code)
+fn is_code_region(kind: Option<RegionKind>) -> bool {
+    matches!(kind, None | Some(RegionKind::Code))
+}
+
+// レジスタの番号 (オペコードの下位3ビット)    6 は (HL)
+const REG: [Reg; 8] = [Reg::B, Reg::C, Reg::D, Reg::E, Reg::H, Reg::L, Reg::F, Reg::A];
+
+// 条件の番号
+const CONDITION: [Condition; 8] = [Condition::NZ, Condition::Z, Condition::NC, Condition::C,
+                                   Condition::PO, Condition::PE, Condition::P, Condition::M];
+
+// 演算命令 ADD A,r など
+const ALU: [Mnemonic; 8] = [Add, Adc, Sub, Sbc, And, Xor, Or, Cp];
+
+// ローテート / シフト命令 (CB 00H-3FH)
+const ROT: [Mnemonic; 8] = [Rlc, Rrc, Rl, Rr, Sla, Sra, Sll, Srl];
+
+// ブロック転送命令 (ED A0H-BBH)
+const BLOCK: [[Mnemonic; 4]; 4] = [[Ldi,  Cpi,  Ini,  Outi],
+                                   [Ldd,  Cpd,  Ind,  Outd],
+                                   [Ldir, Cpir, Inir, Otir],
+                                   [Lddr, Cpdr, Indr, Otdr]];
+
+// **********************************************
+//      逆アセンブラ
+// **********************************************
+pub struct Disassemble {
+    pub org_address: usize,     // ORG アドレス
+    read_address: usize,        // bin_data の読み込みアドレス
+    bin_data: Vec<u8>,          // binary data  
+    result : Vec<Line>,         // 逆アセンブル結果
+    labels: Option<Labels>,     // ラベル (つけない時は None)
+    control: Control,           // コントロールファイルの指定
+    data_options: DataOptions,  // データの出力方法
+    gaps: Vec<(usize, usize)>,  // データのないところ (bin_data のオフセット start..end)
+    entry_points: Vec<u16>,     // ファイルに書いてあった実行開始アドレス
+    system: Option<System>,     // 機種 (決まっているアドレスの名前と、システムコールの注釈)
+
+    // ----- １命令分のテンポラリ -----
+    bytes: Vec<u8>,             // 読み込んだバイト列
+    undocumented: bool,         // 未定義命令なら true
+    index: Option<RegPair>,     // DD / FD プリフィクスの時は IX / IY
+    use_index: bool,            // HL の代わりに IX / IY を使ったら true
+    truncated: bool,            // 命令の途中でデータが終わったら true
+}
+
+impl Disassemble {
+
+    pub fn new(bin_data: Vec<u8>, org_address: usize) -> Self {
+        Disassemble{ org_address,
+                     read_address:0,          // バイナリファイルの読み込み位置
+                     bin_data,                // バイナリファイル
+                     result: Vec::new(),
+                     labels: None,
+                     control: Control::default(),
+                     data_options: DataOptions::default(),
+                     gaps: Vec::new(),
+                     entry_points: Vec::new(),
+                     system: None,
+                     bytes: Vec::new(),
+                     undocumented: false,
+                     index: None,
+                     use_index: false,
+                     truncated: false,
+                   }
+    }
+
+    // ファイルをロードする  形式は拡張子と中身で決める (生のバイナリは org_address に置く)
+    pub fn load_file( &mut self ,filename: &str) -> Result<(), LoadError> {
+        let options = LoadOptions{ org: self.org_address as u16, ..Default::default() };
+        let image = loader::load(filename, &options)?;
+        self.load_image(&image);
+        Ok(())
+    }
+
+    // **********************************************
+    //      読み込んだイメージを置く
+    //      セグメントの間はデータがないので、skip と同じ扱いにして ORG を出し直す
+    //      データの外を指す実行開始アドレス (S9 や HEX の 0000H など) は使わない
+    // **********************************************
+    pub fn load_image(&mut self, image: &Image) {
+        self.entry_points = image.entry_points.iter().copied().filter(|a| image.byte(*a).is_some()).collect();
+        let Some(first) = image.segments.first() else {
+            return;
+        };
+        let start = first.address as usize;
+        let end = image.segments.iter().map(|s| s.address as usize + s.data.len()).max().unwrap_or(start);
+        self.org_address = start;
+        self.bin_data = vec![0; end - start];
+        self.gaps.clear();
+        let mut offset = 0;
+        for segment in &image.segments {
+            let begin = segment.address as usize - start;
+            if begin > offset {
+                self.gaps.push((offset, begin));
+            }
+            self.bin_data[begin..begin + segment.data.len()].copy_from_slice(&segment.data);
+            offset = offset.max(begin + segment.data.len());
+        }
+    }
+
+    // ファイルに書いてあった実行開始アドレス
+    pub fn entry_points(&self) -> &[u16] {
+        &self.entry_points
+    }
+
+    // データのないところか？
+    fn is_gap(&self, offset: usize) -> bool {
+        self.gaps.iter().any(|(start, end)| (*start..*end).contains(&offset))
+    }
+
+    // 機種をセットする
+    pub fn set_system(&mut self, system: System) {
+        self.system = Some(system);
+    }
+
+    // コントロールファイルの指定をセットする
+    pub fn set_control(&mut self, control: Control) {
+        self.control = control;
+    }
+
+    // **********************************************
+    //      最後まで逆アセンブルする
+    //      コントロールファイルでデータにしたところ以外は、すべて命令にする
+    // **********************************************
+    pub fn disassemble_all(&mut self) {
+        let kinds = self.region_kinds();
+        let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+        let mut offset = 0;
+        while offset < self.bin_data.len() {
+            if is_code_region(kinds[offset]) {
+                if let Some(inst) = self.decode_in_code(&kinds, offset) {
+                    offset += inst.size();
+                    code.insert(inst.address, inst);
+                    continue;
+                }
+            }
+            offset += 1;
+        }
+        self.build_lines(&code, &kinds);
+    }
+
+    // **********************************************
+    //      エントリーポイントから実行の流れをたどって逆アセンブルする
+    //      たどり着かなかったところはデータにする
+    //      ファイルの開始アドレスと、コントロールファイルの entry と code の範囲もたどる
+    // **********************************************
+    pub fn disassemble_flow(&mut self, entries: &[u16]) {
+        let org = self.org_address as u16;
+        let kinds = self.region_kinds();
+
+        // ----- code の範囲は、頭から順番に命令にする -----
+        let mut seeds: Vec<u16> = entries.to_vec();
+        seeds.extend(&self.entry_points);
+        seeds.extend(&self.control.entries);
+        let mut offset = 0;
+        while offset < self.bin_data.len() {
+            if kinds[offset] == Some(RegionKind::Code) {
+                if let Some(inst) = self.decode_in_code(&kinds, offset) {
+                    seeds.push(inst.address);       // 後から入れたものが先にたどられる
+                    offset += inst.size();
+                    continue;
+                }
+            }
+            offset += 1;
+        }
+
+        let size = self.bin_data.len();
+        let code = flow::trace(org, size, &seeds, |offset| self.decode_in_code(&kinds, offset));
+        self.build_lines(&code, &kinds);
+    }
+
+    // **********************************************
+    //      コントロールファイルで指定された種類を、１バイトずつの表にする
+    // **********************************************
+    fn region_kinds(&self) -> Vec<Option<RegionKind>> {
+        (0..self.bin_data.len())
+            .map(|offset| if self.is_gap(offset) {
+                Some(RegionKind::Skip)
+            } else {
+                self.control.kind_of(((self.org_address + offset) & 0xffff) as u16)
+            })
+            .collect()
+    }
+
+    // 命令を逆アセンブルする  データの範囲にかかる時は None
+    fn decode_in_code(&mut self, kinds: &[Option<RegionKind>], offset: usize) -> Option<Instruction> {
+        let inst = self.decode(offset)?;
+        if kinds[offset..offset + inst.size()].iter().all(|k| is_code_region(*k)) {
+            Some(inst)
+        } else {
+            None
+        }
+    }
+
+    // **********************************************
+    //      命令とデータを並べて、逆アセンブル結果の行にする
+    // **********************************************
+    fn build_lines(&mut self, code: &BTreeMap<u16, Instruction>, kinds: &[Option<RegionKind>]) {
+        let org = self.org_address as u16;
+
+        // ----- ラベルがつきそうなアドレスでは、データの行を分ける -----
+        let mut splits: BTreeSet<u16> = code.values().filter_map(|i| i.target()).collect();
+        splits.extend(self.control.labels.iter().map(|(a, _)| *a));
+        for region in self.control.regions.iter().filter(|r| r.kind == RegionKind::Pointers) {
+            let mut address = region.start as usize;
+            while address < region.end as usize {
+                let offset = address.wrapping_sub(self.org_address);
+                if let (Some(low), Some(high)) = (self.bin_data.get(offset), self.bin_data.get(offset + 1)) {
+                    splits.insert(u16::from_le_bytes([*low, *high]));
+                }
+                address += 2;
+            }
+        }
+
+        let mut offset = 0;
+        while offset < self.bin_data.len() {
+            let address = org.wrapping_add(offset as u16);
+            if let Some(inst) = code.get(&address) {
+                offset += inst.size();
+                self.result.push(Line::Code(inst.clone()));
+                continue;
+            }
+            // ----- 同じ種類が続くところまでをまとめる -----
+            let kind = kinds[offset];
+            let start = offset;
+            offset += 1;
+            while offset < self.bin_data.len() && kinds[offset] == kind {
+                let address = org.wrapping_add(offset as u16);
+                if code.contains_key(&address) || (kind != Some(RegionKind::Skip) && splits.contains(&address)) {
+                    break;
+                }
+                offset += 1;
+            }
+            let address = org.wrapping_add(start as u16);
+            match kind {
+                Some(RegionKind::Skip)     => self.result.push(Line::Skip { address, size: offset - start }),
+                Some(RegionKind::Words)    => self.push_data(start, offset, DataKind::Words),
+                Some(RegionKind::Pointers) => self.push_data(start, offset, DataKind::Pointers),
+                Some(RegionKind::Text)     => self.push_data(start, offset, DataKind::Text),
+                _                          => self.push_data(start, offset, DataKind::Bytes),
+            }
+        }
+        self.read_address = self.bin_data.len();
+    }
+
+    // **********************************************
+    //      データを１行ずつに分けて入れる  start..end はオフセット
+    // **********************************************
+    fn push_data(&mut self, start: usize, end: usize, kind: DataKind) {
+        let address = ((self.org_address + start) & 0xffff) as u16;
+        let lines = data_lines(address, &self.bin_data[start..end], kind, &self.data_options);
+        self.result.extend(lines);
+    }
+
+    // データの出力方法をセットする
+    pub fn set_data_options(&mut self, options: DataOptions) {
+        self.data_options = options;
+    }
+
+    // 逆アセンブル結果
+    pub fn result(&self) -> &[Line] {
+        &self.result
+    }
+
+    // 逆アセンブル結果のうち、命令だけ
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.result.iter().filter_map(|l| l.instruction())
+    }
+
+    // **********************************************
+    //      飛び先にラベルをつける (逆アセンブルした後で呼ぶ)
+    // **********************************************
+    pub fn make_labels(&mut self, prefix: &str) {
+        let mut labels = Labels::new(prefix);
+        let starts: BTreeSet<u16> = self.result.iter().map(|l| l.address()).collect();
+        for (address, name) in &self.control.labels {
+            if starts.contains(address) {   // 命令の途中にはラベルをつけられない
+                labels.add(*address, name);
+            }
+        }
+        labels.collect(&self.result);
+        if let Some(system) = self.system {
+            labels.collect_symbols(system.symbols(), &system.arguments(&self.result), &self.result);
+        }
+        self.labels = Some(labels);
+    }
+
+    // ラベル
+    pub fn labels(&self) -> Option<&Labels> {
+        self.labels.as_ref()
+    }
+
+    // 行につける注釈 (BDOS の機能の名前など)  アドレス -> 注釈
+    pub fn comments(&self) -> BTreeMap<u16, String> {
+        self.system.map(|s| s.comments(&self.result)).unwrap_or_default()
+    }
+
+    // **********************************************
+    //      １バイト読み込む
+    // **********************************************
+    fn get_byte(&mut self) -> u8 {
+        let Some(&byte) = self.bin_data.get(self.read_address) else {
+            self.truncated = true;              // データが足りない
+            return 0;
+        };
+        self.bytes.push( byte );                // 後で１６進コードを出力するために保存しておく
+        self.read_address+=1;                   // アドレスを足す
+        byte
+    }
+
+    // **********************************************
+    //      WORD で読み込む
+    // **********************************************
+    fn get_word(&mut self) -> u16 {
+        let low:u16 = self.get_byte() as u16; 
+        let high:u16  = self.get_byte() as u16; 
+        high*256+low
+    }
+
+    // **********************************************
+    //      相対ジャンプの飛び先を読み込む
+    // **********************************************
+    fn get_relative(&mut self) -> Operand {
+        let a = self.get_byte() as i8;
+        let address = (self.org_address + self.read_address) as u16;   // 次の命令のアドレス
+        Operand::Address(address.wrapping_add(a as u16))
+    }
+
+    // **********************************************
+    //      未定義命令の印をつける
+    // **********************************************
+    fn undocumented(&mut self) {
+        self.undocumented = true;
+    }
+
+    // **********************************************
+    //      HL (DD / FD の時は IX / IY)
+    // **********************************************
+    fn hl(&mut self) -> RegPair {
+        match self.index {
+            Some(index) => {self.use_index = true; index},
+            None        => RegPair::HL,
+        }
+    }
+
+    // **********************************************
+    //      レジスタ r の番号からオペランドにする
+    //      DD / FD の時は H,L が IXH,IXL に、(HL) が (IX+d) になる
+    // **********************************************
+    fn reg(&mut self, r :u8) -> Operand {
+        match (r, self.index) {
+            (6, None)        => Operand::Indirect(RegPair::HL),
+            (6, Some(index)) => {let d = self.get_byte() as i8;
+                                 self.use_index = true;
+                                 Operand::Indexed(index, d)},
+            (4, Some(index)) => {self.use_index = true;             // 未定義命令
+                                 self.undocumented();
+                                 Operand::Reg(if index == RegPair::IX {Reg::IXH} else {Reg::IYH})},
+            (5, Some(index)) => {self.use_index = true;
+                                 self.undocumented();
+                                 Operand::Reg(if index == RegPair::IX {Reg::IXL} else {Reg::IYL})},
+            _                => Operand::Reg(REG[r as usize]),
+        }
+    }
+
+    // レジスタ r の番号からオペランドにする (IXH などに置き換えない)
+    fn reg_plain(&self, r :u8) -> Operand {
+        Operand::Reg(REG[r as usize])
+    }
+
+    // レジスタペア rp の番号  BC,DE,HL,SP
+    fn reg_pair(&mut self, p :u8) -> Operand {
+        match p {
+            0 => Operand::RegPair(RegPair::BC),
+            1 => Operand::RegPair(RegPair::DE),
+            2 => Operand::RegPair(self.hl()),
+            _ => Operand::RegPair(RegPair::SP),
+        }
+    }
+
+    // レジスタペア rp2 の番号  BC,DE,HL,AF
+    fn reg_pair2(&mut self, p :u8) -> Operand {
+        match p {
+            3 => Operand::RegPair(RegPair::AF),
+            _ => self.reg_pair(p),
+        }
+    }
+
+    // **********************************************
+    //      dd/fd 命令のためのサブルーチン
+    // **********************************************
+    fn ddfd(&mut self, opcode :u8) -> (Mnemonic, Vec<Operand>) {
+        let index = if opcode == 0xdd {RegPair::IX} else {RegPair::IY};
+        let opcode2 = self.get_byte();
+        match opcode2 {
+            0xcb => {let a = self.get_byte() as i8;      // DD CB d op の４バイト命令
+                     let opcode3 = self.get_byte();
+                     self.ddfd_cb(index, a, opcode3)},
+            0xdd | 0xed | 0xfd => {        // 後ろのプリフィクスが優先されるので、このプリフィクスは NOP と同じ
+                    self.read_address -= 1;
+                    self.bytes.pop();
+                    self.undocumented();
+                    (Nop, vec![])},
+            _    => {self.index = Some(index);
+                     let result = self.main_opcode(opcode2);
+                     self.index = None;
+                     if !self.use_index {
+                         self.undocumented();  // HL を使わない命令では、プリフィクスは無視される
+                     }
+                     result},
+        }
+    }
+
+    // **********************************************
+    //      dd cb / fd cb 命令のためのサブルーチン
+    // **********************************************
+    fn ddfd_cb(&mut self, index :RegPair, offset :i8, opcode3 :u8) -> (Mnemonic, Vec<Operand>) {
+        let y = (opcode3 >> 3) & 7;
+        let z = opcode3 & 7;
+        let mut operands = vec![Operand::Indexed(index, offset)];
+        if z != 6 {
+            self.undocumented();
+            if opcode3 & 0xc0 != 0x40 {     // (IX+d) 以外の時は、結果をレジスタにもコピーする (未定義命令)
+                operands.push(self.reg_plain(z));
+            }
+        }
+        match opcode3 >> 6 {
+            0 => {if y == 6 {
+                      self.undocumented();  // SLL
+                  }
+                  (ROT[y as usize], operands)},
+            1 => {operands.insert(0, Operand::Bit(y)); (Bit, operands)}, // BIT はレジスタに書き込まない
+            2 => {operands.insert(0, Operand::Bit(y)); (Res, operands)},
+            _ => {operands.insert(0, Operand::Bit(y)); (Set, operands)},
+        }
+    }
+
+    // **********************************************
+    //      cb 命令のためのサブルーチン
+    // **********************************************
+    fn cb(&mut self) -> (Mnemonic, Vec<Operand>) {
+        let opcode2 = self.get_byte();
+        let y = (opcode2 >> 3) & 7;
+        let reg = self.reg(opcode2 & 7);
+        match opcode2 >> 6 {
+            0 => {if y == 6 {
+                      self.undocumented();  // SLL
+                  }
+                  (ROT[y as usize], vec![reg])},
+            1 => (Bit, vec![Operand::Bit(y), reg]),
+            2 => (Res, vec![Operand::Bit(y), reg]),
+            _ => (Set, vec![Operand::Bit(y), reg]),
+        }
+    }
+
+    // **********************************************
+    //      ed 命令のためのサブルーチン
+    // **********************************************
+    fn ed(&mut self) -> (Mnemonic, Vec<Operand>) {
+        let opcode2 = self.get_byte();
+        let y = (opcode2 >> 3) & 7;
+        let z = opcode2 & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match opcode2 {
+            0x40..=0x7f => match z {
+                0 => {if y == 6 {                  // IN F,(C) フラグだけ変化する
+                          self.undocumented();
+                      }
+                      (In, vec![self.reg_plain(y), Operand::PortC])},
+                1 => if y == 6 {                   // OUT (C),0
+                         self.undocumented();
+                         (Out, vec![Operand::PortC, Operand::Imm8(0)])
+                     } else {
+                         (Out, vec![Operand::PortC, self.reg_plain(y)])
+                     },
+                2 => (if q == 0 {Sbc} else {Adc}, vec![Operand::RegPair(RegPair::HL), self.reg_pair(p)]),
+                3 => {let a = self.get_word();
+                      if p == 2 {
+                          self.undocumented();     // LD (nn),HL の別のコード
+                      }
+                      if q == 0 {
+                          (Ld, vec![Operand::Memory(a), self.reg_pair(p)])
+                      } else {
+                          (Ld, vec![self.reg_pair(p), Operand::Memory(a)])
+                      }},
+                4 => {if y != 0 {
+                          self.undocumented();     // NEG のミラー
+                      }
+                      (Neg, vec![])},
+                5 => if y == 1 {
+                         (Reti, vec![])
+                     } else {
+                         if y != 0 {
+                             self.undocumented();  // RETN のミラー
+                         }
+                         (Retn, vec![])
+                     },
+                6 => {if y != 0 && y != 2 && y != 3 {
+                          self.undocumented();     // IM のミラー
+                      }
+                      let mode = [0, 0, 1, 2][(y & 3) as usize];
+                      (Im, vec![Operand::InterruptMode(mode)])},
+                _ => match y {
+                    0 => (Ld, vec![Operand::Reg(Reg::I), Operand::Reg(Reg::A)]),
+                    1 => (Ld, vec![Operand::Reg(Reg::R), Operand::Reg(Reg::A)]),
+                    2 => (Ld, vec![Operand::Reg(Reg::A), Operand::Reg(Reg::I)]),
+                    3 => (Ld, vec![Operand::Reg(Reg::A), Operand::Reg(Reg::R)]),
+                    4 => (Rrd, vec![]),
+                    5 => (Rld, vec![]),
+                    _ => {self.undocumented(); (Nop, vec![])},
+                },
+            },
+            0xa0..=0xbf if z <= 3 => (BLOCK[(y - 4) as usize][z as usize], vec![]),
+            _ => {self.undocumented(); (Nop, vec![])},  // 何もしない２バイト命令
+        }
+    }
+
+    // **********************************************
+    //      プリフィクスなしの命令
+    //      (DD / FD の後ろの命令もここで逆アセンブルする)
+    // **********************************************
+    fn main_opcode(&mut self, opcode :u8) -> (Mnemonic, Vec<Operand>) {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 7;
+        let z = opcode & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        match opcode {
+            0x00 => (Nop, vec![]),
+            0x08 => (Ex, vec![Operand::RegPair(RegPair::AF), Operand::RegPair(RegPair::AFAlt)]),
+
+            // ======= JR 命令 ===========
+            0x10 => (Djnz, vec![self.get_relative()]),
+            0x18 => (Jr, vec![self.get_relative()]),
+            0x20 | 0x28 | 0x30 | 0x38 => (Jr, vec![Operand::Condition(CONDITION[(y - 4) as usize]), self.get_relative()]),
+
+            0x02 => (Ld, vec![Operand::Indirect(RegPair::BC), Operand::Reg(Reg::A)]),
+            0x12 => (Ld, vec![Operand::Indirect(RegPair::DE), Operand::Reg(Reg::A)]),
+            0x0A => (Ld, vec![Operand::Reg(Reg::A), Operand::Indirect(RegPair::BC)]),
+            0x1A => (Ld, vec![Operand::Reg(Reg::A), Operand::Indirect(RegPair::DE)]),
+            0x22 => {let a = self.get_word();
+                     (Ld, vec![Operand::Memory(a), Operand::RegPair(self.hl())])},
+            0x2A => {let a = self.get_word();
+                     (Ld, vec![Operand::RegPair(self.hl()), Operand::Memory(a)])},
+            0x32 => {let a = self.get_word();
+                     (Ld, vec![Operand::Memory(a), Operand::Reg(Reg::A)])},
+            0x3A => {let a = self.get_word();
+                     (Ld, vec![Operand::Reg(Reg::A), Operand::Memory(a)])},
+
+            0x07 => (Rlca, vec![]),
+            0x0F => (Rrca, vec![]),
+            0x17 => (Rla, vec![]),
+            0x1F => (Rra, vec![]),
+            0x27 => (Daa, vec![]),
+            0x2F => (Cpl, vec![]),
+            0x37 => (Scf, vec![]),
+            0x3F => (Ccf, vec![]),
+
+            0x76 => (Halt, vec![]),
+
+            // ======= 00H-3FH の残り ===========
+            _ if x == 0 => match z {
+                1 => if q == 0 {
+                         let rp = self.reg_pair(p);
+                         let a = self.get_word();
+                         (Ld, vec![rp, Operand::Imm16(a)])
+                     } else {
+                         let hl = self.hl();
+                         (Add, vec![Operand::RegPair(hl), self.reg_pair(p)])
+                     },
+                3 => (if q == 0 {Inc} else {Dec}, vec![self.reg_pair(p)]),
+                4 => (Inc, vec![self.reg(y)]),
+                5 => (Dec, vec![self.reg(y)]),
+                _ => {let r = self.reg(y);                 // LD r,n  (IX+d) の時は d が先に来る
+                      let a = self.get_byte();
+                      (Ld, vec![r, Operand::Imm8(a)])},
+            },
+
+            // ======= LD r,r' ===========
+            _ if x == 1 => if y == 6 {
+                               (Ld, vec![self.reg(y), self.reg_plain(z)])     // LD (IX+d),H は H のまま
+                           } else if z == 6 {
+                               (Ld, vec![self.reg_plain(y), self.reg(z)])
+                           } else {
+                               (Ld, vec![self.reg(y), self.reg(z)])
+                           },
+
+            // ======= 演算命令 ===========
+            _ if x == 2 => {let r = self.reg(z);
+                            self.alu(y, r)},
+
+            // ======= C0H-FFH ===========
+            0xC9 => (Ret, vec![]),
+            0xD9 => (Exx, vec![]),
+            0xE9 => (Jp, vec![Operand::Indirect(self.hl())]),
+            0xF9 => (Ld, vec![Operand::RegPair(RegPair::SP), Operand::RegPair(self.hl())]),
+            0xC3 => (Jp, vec![Operand::Address(self.get_word())]),
+            0xCD => (Call, vec![Operand::Address(self.get_word())]),
+            0xCB => self.cb(),
+            0xED => self.ed(),
+            0xDD | 0xFD => self.ddfd(opcode),
+            0xD3 => (Out, vec![Operand::Port(self.get_byte()), Operand::Reg(Reg::A)]),
+            0xDB => (In, vec![Operand::Reg(Reg::A), Operand::Port(self.get_byte())]),
+            0xE3 => (Ex, vec![Operand::Indirect(RegPair::SP), Operand::RegPair(self.hl())]),
+            0xEB => (Ex, vec![Operand::RegPair(RegPair::DE), Operand::RegPair(RegPair::HL)]),  // DD があっても HL のまま
+            0xF3 => (Di, vec![]),
+            0xFB => (Ei, vec![]),
+            _ => match z {
+                0 => (Ret, vec![Operand::Condition(CONDITION[y as usize])]),
+                1 => (Pop, vec![self.reg_pair2(p)]),
+                2 => (Jp, vec![Operand::Condition(CONDITION[y as usize]), Operand::Address(self.get_word())]),
+                4 => (Call, vec![Operand::Condition(CONDITION[y as usize]), Operand::Address(self.get_word())]),
+                5 => (Push, vec![self.reg_pair2(p)]),
+                6 => {let a = self.get_byte();
+                      self.alu(y, Operand::Imm8(a))},
+                _ => (Rst, vec![Operand::Restart(y * 8)]),
+            },
+        }
+    }
+
+    // **********************************************
+    //      演算命令  ADD,ADC,SBC は A, をつける
+    // **********************************************
+    fn alu(&self, y :u8, operand :Operand) -> (Mnemonic, Vec<Operand>) {
+        let mnemonic = ALU[y as usize];
+        match mnemonic {
+            Add | Adc | Sbc => (mnemonic, vec![Operand::Reg(Reg::A), operand]),
+            _               => (mnemonic, vec![operand]),
+        }
+    }
+
+    // **********************************************
+    //      １命令だけ逆アセンブルする
+    // **********************************************
+    pub fn do_disassemble_one(&mut self){
+        let offset = self.read_address;
+        match self.decode(offset) {
+            Some(inst) => self.result.push(Line::Code(inst)),
+            None       => {                                // 途中で終わった命令は、データとして出力する
+                self.push_data(offset, self.bin_data.len(), DataKind::Bytes);
+                self.read_address = self.bin_data.len();
+            },
+        }
+    }
+
+    // **********************************************
+    //      offset の命令を逆アセンブルする
+    //      途中でデータが終わっていたら None
+    // **********************************************
+    pub(crate) fn decode(&mut self, offset: usize) -> Option<Instruction> {
+        self.read_address = offset;              // 読み込みアドレスをメモっておく
+        self.bytes = Vec::new();                 // オペコード表示用をクリアする
+        self.undocumented = false;
+        self.use_index = false;
+        self.truncated = false;
+
+        let opcode = self.get_byte();
+        let (mnemonic, operands) = self.main_opcode(opcode);
+
+        if self.truncated {
+            return None;
+        }
+        Some( Instruction {
+            address: ((self.org_address + offset) & 0xffff) as u16,
+            bytes: std::mem::take(&mut self.bytes),
+            mnemonic,
+            operands,
+            undocumented: self.undocumented,
+        })
+    }
+
+    // **********************************************
+    //      逆アセンブル結果を出力する
+    // **********************************************
+    pub fn output<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (address, name) in self.labels().iter().flat_map(|l| l.symbols()) {   // 範囲の外の名前
+            writeln!(out, "{:<11} EQU {}", name, format_word(address))?;
+        }
+        writeln!(out, "            ORG {}",format_word(self.org_address as u16))?; // ORG アドレス出力
+        let comments = self.comments();
+
+        for (i, line) in self.result.iter().enumerate() {
+            // ----- ラベルを表示 --------
+            if let Some(name) = self.labels().and_then(|l| l.get(line.address())) {
+                writeln!(out, "{}:", name)?;
+            }
+            // ----- ニーモニックを表示 --------
+            match line {
+                Line::Code(inst) => {
+                    write!(out, "{:<20}",format_instruction(inst, self.labels(), Syntax::Listing))?;
+                },
+                Line::Data { bytes, kind, .. } => {
+                    write!(out, "{:<20}",format_data(bytes, *kind, self.labels(), self.data_options.charset))?;
+                },
+                Line::Skip { address, size } => {   // 飛ばしたところの次から、ORG をつけ直す
+                    if i + 1 < self.result.len() {
+                        writeln!(out, "            ORG {}",format_word(address.wrapping_add(*size as u16)))?;
+                    }
+                    continue;
+                },
+            }
+
+            // ----- 未定義命令なら印をつける ---
+            if line.instruction().is_some_and(|i| i.undocumented) {
+                write!(out, "   *;")?;
+            } else {
+                write!(out, "    ;")?;
+            }
+            // ----- アドレスを表示 ----------
+            write!(out, "{:>04X}:  ",line.address())?;
+            if matches!(line, Line::Data { kind: DataKind::Fill, .. }) {
+                writeln!(out, "-{:04X}", line.address().wrapping_add(line.size() as u16 - 1))?;
+                continue;               // DS はバイト列を表示しない
+            }
+            // ----- 16進数コードを表示 --------
+            for data in line.bytes() {
+                write!(out, "{:02X} ",data)?;
+            }
+            for _n in line.bytes().len()..4 {
+                write!(out, "   ")?;
+            }
+            // ----- キャラクターを表示 --------
+            for data in line.bytes() {
+                write!(out, "{}",TOUTF8[ *data as usize])?;
+            }
+            // ----- 注釈を表示 --------
+            if let Some(comment) = comments.get(&line.address()) {
+                for _n in line.bytes().len()..4 {
+                    write!(out, "  ")?;
+                }
+                write!(out, "  ; {}", comment)?;
+            }
+
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    // **********************************************
+    //      アセンブルし直せるソースを出力する
+    //      skip の範囲も、同じバイナリになるように DB で出力する
+    //      (セグメントの間のデータのないところは、ORG を出し直す)
+    // **********************************************
+    pub fn output_source<W: Write>(&self, out: &mut W, dialect: Dialect) -> io::Result<()> {
+        write_source(out, self.org_address as u16, &self.source_input(), self.labels(), &self.comments(), dialect)
+    }
+
+    // **********************************************
+    //      ソースにする行  逆アセンブルしなかったところ (Skip) は、ファイルの中なら DB にする
+    // **********************************************
+    fn source_input(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for line in &self.result {
+            let Line::Skip { address, size } = line else {
+                lines.push(line.clone());
+                continue;
+            };
+            let start = (*address as usize).wrapping_sub(self.org_address) & 0xffff;
+            let mut offset = start;
+            while offset < start + size {
+                let gap = self.is_gap(offset);
+                let end = (offset..start + size).find(|o| self.is_gap(*o) != gap).unwrap_or(start + size);
+                let address = ((self.org_address + offset) & 0xffff) as u16;
+                if gap {
+                    lines.push(Line::Skip { address, size: end - offset });
+                } else {
+                    lines.extend(data_lines(address, &self.bin_data[offset..end], DataKind::Bytes, &self.data_options));
+                }
+                offset = end;
+            }
+        }
+        lines
+    }
+
+    // **********************************************
+    //      dialect のソースをアセンブルし直して、元のファイルと違うところを探す
+    // **********************************************
+    pub fn verify(&self, dialect: Dialect) -> Vec<Difference> {
+        verify::verify(&self.source_input(), &self.bin_data, self.org_address as u16, self.labels(), dialect)
+    }
+
+    // データの最後に到達したか？
+    pub fn is_finish(& self)-> bool {
+        self.read_address >= self.bin_data.len()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{ihex, srec};
+
+    // 実行開始アドレスがデータの外なら、実行の流れはたどらない
+    #[test]
+    fn entry_points_outside_image() {
+        let mut dasm = Disassemble::new(Vec::new(), 0);
+        dasm.load_image(&srec::parse(b"S10680003E01C971\nS9030000FC\n").unwrap());
+        assert_eq!((dasm.org_address, dasm.entry_points()), (0x8000, &[][..]));
+        dasm.load_image(&ihex::parse(b":038000003E01C975\n:0400000500000000F7\n").unwrap());
+        assert_eq!(dasm.entry_points(), []);
+        dasm.load_image(&srec::parse(b"S10680003E01C971\nS90380017B\n").unwrap());
+        assert_eq!(dasm.entry_points(), [0x8001]);
+    }
+}
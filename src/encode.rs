@@ -0,0 +1,529 @@
+/*   Z80 Disassembler
+     name is encode.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::disassemble_one;
+use crate::instruction::{Condition, Instruction, Mnemonic, Operand, Reg, RegPair};
+use crate::instruction::Mnemonic::*;
+
+// **********************************************
+//      アセンブル (命令 -> バイト列)
+//
+//      命令の表は、Zilog のマニュアルのビットの並び (LD r,r' = 01 rrr rrr など) から作る
+//      逆アセンブラとは別に書いてあるので、最後に逆アセンブルして同じ命令に戻るか確かめると、
+//      どちらかの間違いが見つかる
+// **********************************************
+
+// 命令の形  数値を 0 にした命令
+type Shape = (Mnemonic, Vec<Operand>);
+
+// オペコード
+#[derive(Clone, Debug)]
+struct Template {
+    opcode: Vec<u8>,        // プリフィクスとオペコード (DD CB d op の d は 0)
+    fixed: bool,            // 数値もオペコードに含まれている (OUT (C),0)
+}
+
+// マニュアルの r  (110 は (HL))
+const R: [(u8, Reg); 7] = [(0, Reg::B), (1, Reg::C), (2, Reg::D), (3, Reg::E), (4, Reg::H), (5, Reg::L), (7, Reg::A)];
+
+// マニュアルの cc
+const CC: [Condition; 8] = [Condition::NZ, Condition::Z, Condition::NC, Condition::C,
+                            Condition::PO, Condition::PE, Condition::P, Condition::M];
+
+// 演算 (10 ooo rrr)  と  ローテート / シフト (CB 00 ooo rrr)
+const ALU: [Mnemonic; 8] = [Add, Adc, Sub, Sbc, And, Xor, Or, Cp];
+const ROT: [Mnemonic; 8] = [Rlc, Rrc, Rl, Rr, Sla, Sra, Sll, Srl];
+
+// ブロック転送 (ED 101 ooo cc)
+const BLOCK: [(u8, Mnemonic); 16] = [(0xa0, Ldi), (0xa1, Cpi), (0xa2, Ini), (0xa3, Outi),
+                                     (0xa8, Ldd), (0xa9, Cpd), (0xaa, Ind), (0xab, Outd),
+                                     (0xb0, Ldir), (0xb1, Cpir), (0xb2, Inir), (0xb3, Otir),
+                                     (0xb8, Lddr), (0xb9, Cpdr), (0xba, Indr), (0xbb, Otdr)];
+
+// 短く書くための関数
+fn reg(r: Reg) -> Operand { Operand::Reg(r) }
+fn pair(p: RegPair) -> Operand { Operand::RegPair(p) }
+fn a() -> Operand { Operand::Reg(Reg::A) }
+fn n() -> Operand { Operand::Imm8(0) }
+fn nn() -> Operand { Operand::Imm16(0) }
+fn addr() -> Operand { Operand::Address(0) }
+fn mem() -> Operand { Operand::Memory(0) }
+
+// 演算命令  ADD,ADC,SBC は A, をつける
+fn alu(mnemonic: Mnemonic, operand: Operand) -> Vec<Operand> {
+    match mnemonic {
+        Add | Adc | Sbc => vec![a(), operand],
+        _               => vec![operand],
+    }
+}
+
+// **********************************************
+//      数値のオペランドを 0 にする
+// **********************************************
+fn normalize(operand: &Operand) -> Operand {
+    match operand {
+        Operand::Indexed(p, _) => Operand::Indexed(*p, 0),
+        Operand::Imm8(_)       => Operand::Imm8(0),
+        Operand::Imm16(_)      => Operand::Imm16(0),
+        Operand::Address(_)    => Operand::Address(0),
+        Operand::Memory(_)     => Operand::Memory(0),
+        Operand::Port(_)       => Operand::Port(0),
+        _                      => *operand,
+    }
+}
+
+// 相対ジャンプか？
+fn is_relative(mnemonic: Mnemonic) -> bool {
+    matches!(mnemonic, Mnemonic::Jr | Mnemonic::Djnz)
+}
+
+// **********************************************
+//      オペランドのうしろに続くバイト数 (d は除く)
+// **********************************************
+fn immediate_size(mnemonic: Mnemonic, operands: &[Operand]) -> usize {
+    operands.iter().map(|o| match o {
+        Operand::Imm8(_) | Operand::Port(_)     => 1,
+        Operand::Imm16(_) | Operand::Memory(_)  => 2,
+        Operand::Address(_) if is_relative(mnemonic) => 1,
+        Operand::Address(_)                     => 2,
+        _                                       => 0,
+    }).sum()
+}
+
+// **********************************************
+//      命令の形 -> オペコード の表を作るところ
+// **********************************************
+#[derive(Default)]
+struct Builder {
+    table: HashMap<Shape, Template>,
+}
+
+impl Builder {
+    // 公式の命令  同じ形の未定義命令 (ED 63H の LD (nn),HL など) より優先する
+    fn add(&mut self, opcode: &[u8], mnemonic: Mnemonic, operands: Vec<Operand>) {
+        let template = Template{ opcode: opcode.to_vec(), fixed: false };
+        self.table.insert((mnemonic, operands), template);
+    }
+
+    // 未定義命令  同じ形の公式の命令があれば、そちらを使う
+    fn undocumented(&mut self, opcode: &[u8], mnemonic: Mnemonic, operands: Vec<Operand>) {
+        let template = Template{ opcode: opcode.to_vec(), fixed: false };
+        self.table.entry((mnemonic, operands)).or_insert(template);
+    }
+
+    // **********************************************
+    //      プリフィクスなし
+    // **********************************************
+    fn main(&mut self) {
+        let hl = Operand::Indirect(RegPair::HL);
+        let dd = [RegPair::BC, RegPair::DE, RegPair::HL, RegPair::SP];
+        let qq = [RegPair::BC, RegPair::DE, RegPair::HL, RegPair::AF];
+
+        // ----- 8ビットのロード -----
+        for (r, dst) in R {
+            for (s, src) in R {
+                self.add(&[0x40 | r << 3 | s], Ld, vec![reg(dst), reg(src)]);  // 01 rrr rrr
+            }
+            self.add(&[0x46 | r << 3], Ld, vec![reg(dst), hl]);                 // 01 rrr 110
+            self.add(&[0x70 | r], Ld, vec![hl, reg(dst)]);                      // 01 110 rrr
+            self.add(&[0x06 | r << 3], Ld, vec![reg(dst), n()]);                // 00 rrr 110
+            self.add(&[0x04 | r << 3], Inc, vec![reg(dst)]);                    // 00 rrr 100
+            self.add(&[0x05 | r << 3], Dec, vec![reg(dst)]);                    // 00 rrr 101
+        }
+        self.add(&[0x36], Ld, vec![hl, n()]);
+        self.add(&[0x34], Inc, vec![hl]);
+        self.add(&[0x35], Dec, vec![hl]);
+        self.add(&[0x0a], Ld, vec![a(), Operand::Indirect(RegPair::BC)]);
+        self.add(&[0x1a], Ld, vec![a(), Operand::Indirect(RegPair::DE)]);
+        self.add(&[0x3a], Ld, vec![a(), mem()]);
+        self.add(&[0x02], Ld, vec![Operand::Indirect(RegPair::BC), a()]);
+        self.add(&[0x12], Ld, vec![Operand::Indirect(RegPair::DE), a()]);
+        self.add(&[0x32], Ld, vec![mem(), a()]);
+
+        // ----- 16ビットのロードと演算 -----
+        for (i, p) in dd.into_iter().enumerate() {
+            let i = i as u8;
+            self.add(&[0x01 | i << 4], Ld, vec![pair(p), nn()]);               // 00 dd0 001
+            self.add(&[0x09 | i << 4], Add, vec![pair(RegPair::HL), pair(p)]); // 00 ss1 001
+            self.add(&[0x03 | i << 4], Inc, vec![pair(p)]);                    // 00 ss0 011
+            self.add(&[0x0b | i << 4], Dec, vec![pair(p)]);                    // 00 ss1 011
+        }
+        for (i, p) in qq.into_iter().enumerate() {
+            let i = i as u8;
+            self.add(&[0xc5 | i << 4], Push, vec![pair(p)]);                   // 11 qq0 101
+            self.add(&[0xc1 | i << 4], Pop, vec![pair(p)]);                    // 11 qq0 001
+        }
+        self.add(&[0x2a], Ld, vec![pair(RegPair::HL), mem()]);
+        self.add(&[0x22], Ld, vec![mem(), pair(RegPair::HL)]);
+        self.add(&[0xf9], Ld, vec![pair(RegPair::SP), pair(RegPair::HL)]);
+
+        // ----- 交換 -----
+        self.add(&[0xeb], Ex, vec![pair(RegPair::DE), pair(RegPair::HL)]);
+        self.add(&[0x08], Ex, vec![pair(RegPair::AF), pair(RegPair::AFAlt)]);
+        self.add(&[0xd9], Exx, vec![]);
+        self.add(&[0xe3], Ex, vec![Operand::Indirect(RegPair::SP), pair(RegPair::HL)]);
+
+        // ----- 8ビットの演算 -----
+        for (o, mnemonic) in ALU.into_iter().enumerate() {
+            let o = o as u8;
+            for (r, src) in R {
+                self.add(&[0x80 | o << 3 | r], mnemonic, alu(mnemonic, reg(src)));   // 10 ooo rrr
+            }
+            self.add(&[0x86 | o << 3], mnemonic, alu(mnemonic, hl));                 // 10 ooo 110
+            self.add(&[0xc6 | o << 3], mnemonic, alu(mnemonic, n()));                // 11 ooo 110
+        }
+
+        // ----- そのほか -----
+        let simple = [(0x00, Nop), (0x76, Halt), (0xf3, Di), (0xfb, Ei),
+                      (0x07, Rlca), (0x17, Rla), (0x0f, Rrca), (0x1f, Rra),
+                      (0x27, Daa), (0x2f, Cpl), (0x37, Scf), (0x3f, Ccf), (0xc9, Ret)];
+        for (opcode, mnemonic) in simple {
+            self.add(&[opcode], mnemonic, vec![]);
+        }
+
+        // ----- ジャンプ・コール -----
+        self.add(&[0xc3], Jp, vec![addr()]);
+        self.add(&[0xe9], Jp, vec![hl]);
+        self.add(&[0x18], Jr, vec![addr()]);
+        self.add(&[0x10], Djnz, vec![addr()]);
+        self.add(&[0xcd], Call, vec![addr()]);
+        for (c, condition) in CC.into_iter().enumerate() {
+            let c = c as u8;
+            let cc = Operand::Condition(condition);
+            self.add(&[0xc2 | c << 3], Jp, vec![cc, addr()]);      // 11 ccc 010
+            self.add(&[0xc4 | c << 3], Call, vec![cc, addr()]);    // 11 ccc 100
+            self.add(&[0xc0 | c << 3], Ret, vec![cc]);             // 11 ccc 000
+            if c < 4 {
+                self.add(&[0x20 | c << 3], Jr, vec![cc, addr()]);  // 001 cc 000  (NZ,Z,NC,C だけ)
+            }
+        }
+        for t in 0..8u8 {
+            self.add(&[0xc7 | t << 3], Rst, vec![Operand::Restart(t * 8)]);    // 11 ttt 111
+        }
+
+        // ----- 入出力 -----
+        self.add(&[0xdb], In, vec![a(), Operand::Port(0)]);
+        self.add(&[0xd3], Out, vec![Operand::Port(0), a()]);
+    }
+
+    // **********************************************
+    //      CB  ローテート・シフト・ビット操作
+    // **********************************************
+    fn cb(&mut self) {
+        let targets = R.into_iter().map(|(r, rr)| (r, reg(rr))).chain([(6, Operand::Indirect(RegPair::HL))]);
+        for (r, target) in targets {
+            for (o, mnemonic) in ROT.into_iter().enumerate() {
+                let opcode = [0xcb, (o as u8) << 3 | r];                      // CB 00 ooo rrr
+                if mnemonic == Sll {
+                    self.undocumented(&opcode, mnemonic, vec![target]);
+                } else {
+                    self.add(&opcode, mnemonic, vec![target]);
+                }
+            }
+            for b in 0..8u8 {
+                let bit = Operand::Bit(b);
+                self.add(&[0xcb, 0x40 | b << 3 | r], Bit, vec![bit, target]);  // CB 01 bbb rrr
+                self.add(&[0xcb, 0x80 | b << 3 | r], Res, vec![bit, target]);  // CB 10 bbb rrr
+                self.add(&[0xcb, 0xc0 | b << 3 | r], Set, vec![bit, target]);  // CB 11 bbb rrr
+            }
+        }
+    }
+
+    // **********************************************
+    //      ED
+    // **********************************************
+    fn ed(&mut self) {
+        let c = Operand::PortC;
+        for (r, rr) in R {
+            self.add(&[0xed, 0x40 | r << 3], In, vec![reg(rr), c]);     // ED 01 rrr 000
+            self.add(&[0xed, 0x41 | r << 3], Out, vec![c, reg(rr)]);    // ED 01 rrr 001
+        }
+        self.undocumented(&[0xed, 0x70], In, vec![reg(Reg::F), c]);
+        self.table.insert((Out, vec![c, Operand::Imm8(0)]),
+                          Template{ opcode: vec![0xed, 0x71], fixed: true });
+
+        let ss = [RegPair::BC, RegPair::DE, RegPair::HL, RegPair::SP];
+        for (i, p) in ss.into_iter().enumerate() {
+            let i = i as u8;
+            self.add(&[0xed, 0x42 | i << 4], Sbc, vec![pair(RegPair::HL), pair(p)]);   // ED 01 ss0 010
+            self.add(&[0xed, 0x4a | i << 4], Adc, vec![pair(RegPair::HL), pair(p)]);   // ED 01 ss1 010
+            if p == RegPair::HL {
+                self.undocumented(&[0xed, 0x63], Ld, vec![mem(), pair(p)]);
+                self.undocumented(&[0xed, 0x6b], Ld, vec![pair(p), mem()]);
+            } else {
+                self.add(&[0xed, 0x43 | i << 4], Ld, vec![mem(), pair(p)]);            // ED 01 dd0 011
+                self.add(&[0xed, 0x4b | i << 4], Ld, vec![pair(p), mem()]);            // ED 01 dd1 011
+            }
+        }
+
+        self.add(&[0xed, 0x44], Neg, vec![]);
+        self.add(&[0xed, 0x45], Retn, vec![]);
+        self.add(&[0xed, 0x4d], Reti, vec![]);
+        self.add(&[0xed, 0x46], Im, vec![Operand::InterruptMode(0)]);
+        self.add(&[0xed, 0x56], Im, vec![Operand::InterruptMode(1)]);
+        self.add(&[0xed, 0x5e], Im, vec![Operand::InterruptMode(2)]);
+        self.add(&[0xed, 0x47], Ld, vec![reg(Reg::I), a()]);
+        self.add(&[0xed, 0x4f], Ld, vec![reg(Reg::R), a()]);
+        self.add(&[0xed, 0x57], Ld, vec![a(), reg(Reg::I)]);
+        self.add(&[0xed, 0x5f], Ld, vec![a(), reg(Reg::R)]);
+        self.add(&[0xed, 0x67], Rrd, vec![]);
+        self.add(&[0xed, 0x6f], Rld, vec![]);
+        for (opcode, mnemonic) in BLOCK {
+            self.add(&[0xed, opcode], mnemonic, vec![]);
+        }
+    }
+
+    // **********************************************
+    //      DD / FD  HL の代わりに IX / IY を使う
+    //      H,L を IXH,IXL にするものは未定義命令
+    // **********************************************
+    fn index(&mut self, prefix: u8, index: RegPair, high: Reg, low: Reg) {
+        let x = Operand::Indexed(index, 0);
+        let ix = pair(index);
+
+        // ----- 16ビット -----
+        self.add(&[prefix, 0x21], Ld, vec![ix, nn()]);
+        self.add(&[prefix, 0x2a], Ld, vec![ix, mem()]);
+        self.add(&[prefix, 0x22], Ld, vec![mem(), ix]);
+        self.add(&[prefix, 0xf9], Ld, vec![pair(RegPair::SP), ix]);
+        self.add(&[prefix, 0xe5], Push, vec![ix]);
+        self.add(&[prefix, 0xe1], Pop, vec![ix]);
+        self.add(&[prefix, 0xe3], Ex, vec![Operand::Indirect(RegPair::SP), ix]);
+        self.add(&[prefix, 0x23], Inc, vec![ix]);
+        self.add(&[prefix, 0x2b], Dec, vec![ix]);
+        self.add(&[prefix, 0xe9], Jp, vec![Operand::Indirect(index)]);
+        for (i, p) in [RegPair::BC, RegPair::DE, index, RegPair::SP].into_iter().enumerate() {
+            self.add(&[prefix, 0x09 | (i as u8) << 4], Add, vec![ix, pair(p)]);    // DD 00 pp1 001
+        }
+
+        // ----- (IX+d) -----
+        for (r, rr) in R {
+            self.add(&[prefix, 0x46 | r << 3], Ld, vec![reg(rr), x]);      // DD 01 rrr 110 d
+            self.add(&[prefix, 0x70 | r], Ld, vec![x, reg(rr)]);           // DD 01 110 rrr d
+        }
+        self.add(&[prefix, 0x36], Ld, vec![x, n()]);
+        self.add(&[prefix, 0x34], Inc, vec![x]);
+        self.add(&[prefix, 0x35], Dec, vec![x]);
+        for (o, mnemonic) in ALU.into_iter().enumerate() {
+            self.add(&[prefix, 0x86 | (o as u8) << 3], mnemonic, alu(mnemonic, x));
+        }
+
+        // ----- IXH / IXL (未定義命令) -----
+        let half = |r: Reg| match r {
+            Reg::H => high,
+            Reg::L => low,
+            _      => r,
+        };
+        for (r, rr) in R {
+            for (s, ss) in R {
+                if matches!(rr, Reg::H | Reg::L) || matches!(ss, Reg::H | Reg::L) {
+                    self.undocumented(&[prefix, 0x40 | r << 3 | s], Ld, vec![reg(half(rr)), reg(half(ss))]);
+                }
+            }
+        }
+        for (r, rr) in [(4, high), (5, low)] {
+            self.undocumented(&[prefix, 0x06 | r << 3], Ld, vec![reg(rr), n()]);
+            self.undocumented(&[prefix, 0x04 | r << 3], Inc, vec![reg(rr)]);
+            self.undocumented(&[prefix, 0x05 | r << 3], Dec, vec![reg(rr)]);
+            for (o, mnemonic) in ALU.into_iter().enumerate() {
+                self.undocumented(&[prefix, 0x80 | (o as u8) << 3 | r], mnemonic, alu(mnemonic, reg(rr)));
+            }
+        }
+
+        // ----- DD CB d op -----
+        for (o, mnemonic) in ROT.into_iter().enumerate() {
+            let op = (o as u8) << 3;
+            if mnemonic == Sll {
+                self.undocumented(&[prefix, 0xcb, 0, op | 6], mnemonic, vec![x]);
+            } else {
+                self.add(&[prefix, 0xcb, 0, op | 6], mnemonic, vec![x]);
+            }
+            for (r, rr) in R {      // 結果をレジスタにもコピーする
+                self.undocumented(&[prefix, 0xcb, 0, op | r], mnemonic, vec![x, reg(rr)]);
+            }
+        }
+        for b in 0..8u8 {
+            let bit = Operand::Bit(b);
+            self.add(&[prefix, 0xcb, 0, 0x46 | b << 3], Bit, vec![bit, x]);
+            self.add(&[prefix, 0xcb, 0, 0x86 | b << 3], Res, vec![bit, x]);
+            self.add(&[prefix, 0xcb, 0, 0xc6 | b << 3], Set, vec![bit, x]);
+            for (r, rr) in R {
+                self.undocumented(&[prefix, 0xcb, 0, 0x80 | b << 3 | r], Res, vec![bit, x, reg(rr)]);
+                self.undocumented(&[prefix, 0xcb, 0, 0xc0 | b << 3 | r], Set, vec![bit, x, reg(rr)]);
+            }
+        }
+    }
+}
+
+// **********************************************
+//      命令の形 -> オペコード の表
+// **********************************************
+fn table() -> &'static HashMap<Shape, Template> {
+    static TABLE: OnceLock<HashMap<Shape, Template>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut builder = Builder::default();
+        builder.main();
+        builder.cb();
+        builder.ed();
+        builder.index(0xdd, RegPair::IX, Reg::IXH, Reg::IXL);
+        builder.index(0xfd, RegPair::IY, Reg::IYH, Reg::IYL);
+        builder.table
+    })
+}
+
+// **********************************************
+//      命令の形を表から探す
+//      OUT (C),0 のように数値も決まっている命令は、数値まで同じものだけ
+// **********************************************
+fn lookup(mnemonic: Mnemonic, operands: &[Operand]) -> Option<&'static Template> {
+    let table = table();
+    let normalized: Vec<Operand> = operands.iter().map(normalize).collect();
+    table.get(&(mnemonic, operands.to_vec()))
+        .filter(|t| t.fixed)
+        .or_else(|| table.get(&(mnemonic, normalized)).filter(|t| !t.fixed))
+}
+
+// **********************************************
+//      命令をバイト列にする
+//      inst.address は相対ジャンプの計算に使う
+// **********************************************
+pub fn encode(inst: &Instruction) -> Result<Vec<u8>, String> {
+    let template = lookup(inst.mnemonic, &inst.operands)
+        .ok_or_else(|| format!("no such instruction: {}", inst))?;
+
+    // OUT (C),0 のような命令は数値を埋め込まない
+    let mut bytes = template.opcode.clone();
+    let is_ddcb = bytes.len() == 4;
+    // ----- (IX+d) の d -----
+    for operand in inst.operands.iter().filter(|_| !template.fixed) {
+        if let Operand::Indexed(_, d) = operand {
+            if is_ddcb {
+                bytes[2] = *d as u8;
+            } else {
+                bytes.push(*d as u8);
+            }
+        }
+    }
+    // ----- 数値 -----
+    let size = bytes.len() + immediate_size(inst.mnemonic, &inst.operands);
+    for operand in inst.operands.iter().filter(|_| !template.fixed) {
+        match operand {
+            Operand::Imm8(n) | Operand::Port(n) => bytes.push(*n),
+            Operand::Imm16(n) | Operand::Memory(n) => bytes.extend(n.to_le_bytes()),
+            Operand::Address(a) if is_relative(inst.mnemonic) => {
+                let next = inst.address.wrapping_add(size as u16);
+                let offset = a.wrapping_sub(next) as i16;
+                if !(-128..=127).contains(&offset) {
+                    return Err(format!("relative jump out of range: {}", inst));
+                }
+                bytes.push(offset as u8);
+            },
+            Operand::Address(a) => bytes.extend(a.to_le_bytes()),
+            _ => {},
+        }
+    }
+
+    // ----- 逆アセンブルして、同じ命令に戻るか確かめる (表と逆アセンブラの食い違いを見つける) -----
+    match disassemble_one(&bytes, inst.address) {
+        Some(check) if check.size() == bytes.len()
+                    && check.mnemonic == inst.mnemonic
+                    && check.operands == inst.operands => Ok(bytes),
+        _ => Err(format!("cannot encode: {}", inst)),
+    }
+}
+
+// **********************************************
+//      アセンブラで同じバイト列に戻せる命令か？
+//      NEG のミラーや、意味のない DD プリフィクスなどは戻せない
+// **********************************************
+pub fn is_canonical(inst: &Instruction) -> bool {
+    encode(inst).is_ok_and(|bytes| bytes == inst.bytes)
+}
+
+// **********************************************
+//      その形の命令があるか？ (アセンブラがオペランドの種類を決めるのに使う)
+// **********************************************
+pub(crate) fn has_shape(mnemonic: Mnemonic, operands: &[Operand]) -> bool {
+    lookup(mnemonic, operands).is_some()
+}
+
+// **********************************************
+//      ニーモニックの名前 (LD など) から Mnemonic にする
+// **********************************************
+pub(crate) fn mnemonic_from_name(name: &str) -> Option<Mnemonic> {
+    static NAMES: OnceLock<HashMap<String, Mnemonic>> = OnceLock::new();
+    let names = NAMES.get_or_init(|| {
+        table().keys().map(|(m, _)| (m.to_string(), *m)).collect()
+    });
+    names.get(&name.to_ascii_uppercase()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 定義された命令は、すべて同じバイト列に戻る (表と逆アセンブラが別々に作られているのを確かめる)
+    #[test]
+    fn encode_every_documented_opcode() {
+        let pages: &[&[u8]] = &[&[], &[0xcb], &[0xed], &[0xdd], &[0xfd], &[0xdd, 0xcb, 0x05], &[0xfd, 0xcb, 0x05]];
+        for page in pages {
+            for op in 0..=255u8 {
+                let mut bytes = page.to_vec();
+                bytes.extend([op, 0x05, 0x34, 0x12]);
+                let Some(inst) = disassemble_one(&bytes, 0x1000) else { continue };
+                if inst.undocumented {
+                    continue;
+                }
+                assert_eq!(encode(&inst), Ok(inst.bytes.clone()), "{}", inst);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_fixed_and_relative() {
+        let out = disassemble_one(&[0xed, 0x71], 0).unwrap();
+        assert_eq!(encode(&out), Ok(vec![0xed, 0x71]));
+        let mut bad = out.clone();
+        bad.operands[1] = Operand::Imm8(1);         // OUT (C),1 という命令はない
+        assert!(encode(&bad).is_err());
+
+        let mut jr = disassemble_one(&[0x18, 0x00], 0x1000).unwrap();
+        jr.operands = vec![Operand::Address(0x1081)];
+        assert_eq!(encode(&jr), Ok(vec![0x18, 0x7f]));
+        jr.operands = vec![Operand::Address(0x1082)];
+        assert!(encode(&jr).is_err());
+    }
+
+    // 意味のない DD プリフィクスは戻せない
+    #[test]
+    fn canonical() {
+        assert!(is_canonical(&disassemble_one(&[0xdd, 0x7e, 0x05], 0).unwrap()));
+        assert!(!is_canonical(&disassemble_one(&[0xed, 0x4c], 0).unwrap()));      // NEG のミラー
+    }
+}
@@ -0,0 +1,132 @@
+/*   Z80 Disassembler
+     name is flow.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+
+use crate::instruction::{Instruction, Mnemonic};
+
+// **********************************************
+//      命令の後ろに実行が続くか？
+//      無条件の JP / JR / RET と JP (HL) の後ろは続かない
+// **********************************************
+pub fn falls_through(inst: &Instruction) -> bool {
+    if inst.is_conditional() {
+        return true;
+    }
+    !matches!(inst.mnemonic, Mnemonic::Jp | Mnemonic::Jr | Mnemonic::Ret | Mnemonic::Reti | Mnemonic::Retn)
+}
+
+// **********************************************
+//      エントリーポイントから実行の流れをたどる
+//      org     データの先頭のアドレス
+//      size    データのバイト数
+//      decode  オフセットを渡すと、そこの命令を返す (データが足りなければ None)
+//      見つかった命令をアドレス順に返す
+// **********************************************
+pub fn trace<F>(org: u16, size: usize, entries: &[u16], mut decode: F) -> BTreeMap<u16, Instruction>
+    where F: FnMut(usize) -> Option<Instruction>
+{
+    let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut used = vec![false; size];       // 命令に使われているバイト
+    let mut pending: Vec<u16> = entries.to_vec();
+
+    while let Some(address) = pending.pop() {
+        let offset = address.wrapping_sub(org) as usize;
+        if offset >= size || code.contains_key(&address) {
+            continue;                       // 範囲外か、もうたどった
+        }
+        let Some(inst) = decode(offset) else {
+            continue;                       // 途中でデータが終わっている
+        };
+        let end = offset + inst.size();
+        if end > size || used[offset..end].iter().any(|u| *u) {
+            continue;                       // 他の命令と重なっているので、コードではない
+        }
+        used[offset..end].iter_mut().for_each(|u| *u = true);
+
+        // ----- 飛び先をたどる -----
+        if let Some(target) = inst.target() {
+            pending.push(target);
+        }
+        // ----- 次の命令をたどる -----
+        if falls_through(&inst) {
+            pending.push(inst.next_address());
+        }
+        code.insert(address, inst);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_one;
+
+    const ORG: u16 = 0x8000;
+    const PROGRAM: [u8; 18] = [
+        0xcd, 0x0b, 0x80,       // 8000  CALL  800BH
+        0x28, 0x03,             // 8003  JR    Z,8008H
+        0xc3, 0x0f, 0x80,       // 8005  JP    800FH
+        0xc9,                   // 8008  RET
+        0x41, 0x42,             // 8009  データ
+        0xff,                   // 800B  RST   38H          飛び先は範囲の外
+        0x10, 0xfd,             // 800C  DJNZ  800BH
+        0xc9,                   // 800E  RET
+        0xe9,                   // 800F  JP    (HL)
+        0x00, 0x00,             // 8010  データ
+    ];
+
+    fn addresses(entries: &[u16]) -> Vec<u16> {
+        let code = trace(ORG, PROGRAM.len(), entries, |offset| disassemble_one(&PROGRAM[offset..], ORG + offset as u16));
+        code.keys().copied().collect()
+    }
+
+    #[test]
+    fn follows_branches() {
+        assert_eq!(addresses(&[ORG]), [0x8000, 0x8003, 0x8005, 0x8008, 0x800b, 0x800c, 0x800e, 0x800f]);
+    }
+
+    // すでに命令になっているところと重なるエントリーポイントは使わない
+    #[test]
+    fn overlapping_entry() {
+        assert_eq!(addresses(&[0x8001, ORG]), addresses(&[ORG]));
+        assert_eq!(addresses(&[0x8009, 0x9000]), [0x8009, 0x800a, 0x800b, 0x800c, 0x800e]);     // 範囲の外は読まない
+    }
+
+    #[test]
+    fn fall_through() {
+        let falls = |bytes: &[u8]| falls_through(&disassemble_one(bytes, ORG).unwrap());
+        assert!(falls(&[0xcd, 0x00, 0x00]));            // CALL
+        assert!(falls(&[0xc2, 0x00, 0x00]));            // JP    NZ
+        assert!(falls(&[0xc8]));                        // RET   Z
+        assert!(falls(&[0x10, 0x00]));                  // DJNZ
+        assert!(!falls(&[0xc3, 0x00, 0x00]));           // JP
+        assert!(!falls(&[0x18, 0x00]));                 // JR
+        assert!(!falls(&[0xc9]));                       // RET
+        assert!(!falls(&[0xed, 0x4d]));                 // RETI
+        assert!(!falls(&[0xe9]));                       // JP    (HL)
+    }
+}
@@ -0,0 +1,335 @@
+/*   Z80 Disassembler
+     name is format.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::fmt;
+
+use crate::charcode::Charset;
+use crate::instruction::{Condition, Instruction, Mnemonic, Operand, Reg, RegPair};
+use crate::label::Labels;
+use crate::line::DataKind;
+
+// **********************************************
+//      2バイト数値をフォーマットする
+// **********************************************
+pub fn format_word(address:u16) -> String {
+    let s = format!("{:04X}H",address);
+    let ch = s.chars().next().unwrap(); // １６進数の文字列にしてみて一文字目を抜き出す
+    if ch.is_ascii_digit() {
+        s
+    }else {
+        format!("0{}",s)
+    }
+}
+
+// **********************************************
+//      バイト数値をフォーマットする
+// **********************************************
+pub fn format_byte(value:u8) -> String {
+    let s = format!("{:02X}H",value);
+    let ch = s.chars().next().unwrap(); // １６進数の文字列にしてみて一文字目を抜き出す
+    if ch.is_ascii_digit() {
+        s
+    }else {
+        format!("0{}",s)
+    }
+}
+
+// **********************************************
+//      バイト数値をフラグつきの10進数でフォーマットする
+// **********************************************
+pub fn format_signed_decimal(value:i8) -> String {
+    if value >= 0 {
+        format!("+{}",value)
+    } else {
+        format!("-{}",value.unsigned_abs())
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Reg::A   => "A",
+            Reg::B   => "B",
+            Reg::C   => "C",
+            Reg::D   => "D",
+            Reg::E   => "E",
+            Reg::H   => "H",
+            Reg::L   => "L",
+            Reg::F   => "F",
+            Reg::I   => "I",
+            Reg::R   => "R",
+            Reg::IXH => "IXH",
+            Reg::IXL => "IXL",
+            Reg::IYH => "IYH",
+            Reg::IYL => "IYL",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for RegPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            RegPair::BC    => "BC",
+            RegPair::DE    => "DE",
+            RegPair::HL    => "HL",
+            RegPair::SP    => "SP",
+            RegPair::AF    => "AF",
+            RegPair::AFAlt => "AF'",
+            RegPair::IX    => "IX",
+            RegPair::IY    => "IY",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Condition::NZ => "NZ",
+            Condition::Z  => "Z",
+            Condition::NC => "NC",
+            Condition::C  => "C",
+            Condition::PO => "PO",
+            Condition::PE => "PE",
+            Condition::P  => "P",
+            Condition::M  => "M",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Debug 表示 (Ldir など) を大文字にしたものが、そのままニーモニックになる
+        f.write_str(&format!("{:?}", self).to_uppercase())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(r)           => write!(f, "{}", r),
+            Operand::RegPair(p)       => write!(f, "{}", p),
+            Operand::Indirect(p)      => write!(f, "({})", p),
+            Operand::Indexed(p, d)    => write!(f, "({}{}D)", p, format_signed_decimal(*d)),
+            Operand::Imm8(n)          => write!(f, "{}", format_byte(*n)),
+            Operand::Imm16(n)         => write!(f, "{}", format_word(*n)),
+            Operand::Address(a)       => write!(f, "{}", format_word(*a)),
+            Operand::Memory(a)        => write!(f, "({})", format_word(*a)),
+            Operand::Port(n)          => write!(f, "({})", format_byte(*n)),
+            Operand::PortC            => write!(f, "(C)"),
+            Operand::Condition(c)     => write!(f, "{}", c),
+            Operand::Bit(n)           => write!(f, "{}", n),
+            Operand::InterruptMode(n) => write!(f, "{}", n),
+            Operand::Restart(n)       => write!(f, "{}", format_byte(*n)),
+        }
+    }
+}
+
+// **********************************************
+//      書き方
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Syntax {
+    #[default]
+    Listing,        // 逆アセンブルリスト  (IX+5D)
+    Source,         // アセンブラのソース  (IX+5)
+}
+
+// **********************************************
+//      オペランドをフォーマットする
+//      飛び先にラベルがあれば、ラベル名にする
+//      LD A,(nn) は、機種で決まっている変数の名前だけにする  LD DE,nn の nn は数値のまま
+// **********************************************
+pub fn format_operand(operand: &Operand, labels: Option<&Labels>, syntax: Syntax) -> String {
+    match (operand, labels) {
+        (Operand::Address(a), Some(labels)) => match labels.get(*a) {
+            Some(name) => name.to_string(),
+            None       => operand.to_string(),
+        },
+        (Operand::Memory(a), Some(labels)) => match labels.value(*a) {
+            Some(name) => format!("({})", name),
+            None       => operand.to_string(),
+        },
+        (Operand::Indexed(p, d), _) if syntax == Syntax::Source => {
+            if *d >= 0 {
+                format!("({}+{})", p, d)
+            } else {
+                format!("({}-{})", p, d.unsigned_abs())
+            }
+        },
+        _ => operand.to_string(),
+    }
+}
+
+// **********************************************
+//      命令をフォーマットする  LD    A,(HL)
+//      システムコールに渡す LD DE,nn だけは nn も名前にする
+// **********************************************
+pub fn format_instruction(inst: &Instruction, labels: Option<&Labels>, syntax: Syntax) -> String {
+    if inst.operands.is_empty() {
+        return inst.mnemonic.to_string();
+    }
+    let operands:Vec<String> = inst.operands.iter().map(|o| match (o, labels) {
+        (Operand::Imm16(n), Some(labels)) => match labels.argument(inst.address, *n) {
+            Some(name) => name.to_string(),
+            None       => o.to_string(),
+        },
+        _ => format_operand(o, labels, syntax),
+    }).collect();
+    format!("{:<6}{}", inst.mnemonic.to_string(), operands.join(","))
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_instruction(self, None, Syntax::Listing))
+    }
+}
+
+// **********************************************
+//      データをフォーマットする  DB / DW / DS
+// **********************************************
+pub fn format_data(bytes: &[u8], kind: DataKind, labels: Option<&Labels>, charset: Charset) -> String {
+    let directive = match kind {
+        DataKind::Words | DataKind::Pointers => "DW",
+        DataKind::Fill                       => "DS",
+        _                                    => "DB",
+    };
+    let items = format_data_items(bytes, kind, labels, charset, '"');
+    format!("{:<6}{}", directive, items.join(","))
+}
+
+// **********************************************
+//      DB / DW / DS のうしろに並べるもの
+//      DS は 個数 (0 以外で埋める時は 個数,値)
+//      文字列は quote でくくる
+// **********************************************
+pub fn format_data_items(bytes: &[u8], kind: DataKind, labels: Option<&Labels>, charset: Charset, quote: char) -> Vec<String> {
+    match kind {
+        DataKind::Bytes    => bytes.iter().map(|b| format_byte(*b)).collect(),
+        DataKind::Words    => bytes.chunks_exact(2)
+                                   .map(|w| format_word(u16::from_le_bytes([w[0], w[1]])))
+                                   .collect(),
+        DataKind::Pointers => bytes.chunks_exact(2)
+                                   .map(|w| format_operand(&Operand::Address(u16::from_le_bytes([w[0], w[1]])), labels, Syntax::Listing))
+                                   .collect(),
+        DataKind::Text     => format_text(bytes, charset, quote),
+        DataKind::Fill     => match bytes.first() {
+            Some(0) | None => vec![bytes.len().to_string()],
+            Some(b)        => vec![bytes.len().to_string(), format_byte(*b)],
+        },
+    }
+}
+
+// **********************************************
+//      文字列  表示できる文字は quote でくくる
+//      quote と \ は、アセンブラによって意味が変わるのでバイトにする
+// **********************************************
+fn format_text(bytes: &[u8], charset: Charset, quote: char) -> Vec<String> {
+    let mut items:Vec<String> = Vec::new();
+    let mut text = String::new();
+    for b in bytes {
+        match charset.to_text(*b) {
+            Some(ch) if !ch.starts_with(quote) && ch != "\\" => text.push_str(ch),
+            _ => {
+                if !text.is_empty() {
+                    items.push(format!("{}{}{}", quote, text, quote));
+                    text.clear();
+                }
+                items.push(format_byte(*b));
+            },
+        }
+    }
+    if !text.is_empty() {
+        items.push(format!("{}{}{}", quote, text, quote));
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A-F で始まる数値は 0 をつける
+    #[test]
+    fn numbers() {
+        assert_eq!(format_word(0x1234), "1234H");
+        assert_eq!(format_word(0xc000), "0C000H");
+        assert_eq!(format_byte(0x05), "05H");
+        assert_eq!(format_byte(0xff), "0FFH");
+        assert_eq!(format_signed_decimal(5), "+5");
+        assert_eq!(format_signed_decimal(0), "+0");
+        assert_eq!(format_signed_decimal(-128), "-128");
+    }
+
+    #[test]
+    fn operands() {
+        let cases = [
+            (Operand::RegPair(RegPair::AFAlt),      "AF'"),
+            (Operand::Indirect(RegPair::HL),        "(HL)"),
+            (Operand::Indexed(RegPair::IY, -2),     "(IY-2D)"),
+            (Operand::Memory(0xf000),               "(0F000H)"),
+            (Operand::Port(0xa8),                   "(0A8H)"),
+            (Operand::PortC,                        "(C)"),
+            (Operand::Condition(Condition::PE),     "PE"),
+            (Operand::Restart(0x38),                "38H"),
+        ];
+        for (operand, text) in cases {
+            assert_eq!(operand.to_string(), text);
+        }
+    }
+
+    // ニーモニックは 6 文字の幅にそろえる
+    #[test]
+    fn instruction() {
+        let inst = |mnemonic, operands| Instruction{ address: 0, bytes: Vec::new(), mnemonic, operands, undocumented: false };
+        assert_eq!(inst(Mnemonic::Ldir, vec![]).to_string(), "LDIR");
+        assert_eq!(inst(Mnemonic::Ld, vec![Operand::Reg(Reg::A), Operand::Imm8(0x12)]).to_string(), "LD    A,12H");
+        assert_eq!(inst(Mnemonic::Jp, vec![Operand::Condition(Condition::NZ), Operand::Address(0x8000)]).to_string(), "JP    NZ,8000H");
+    }
+
+    // 飛び先にラベルがあれば、ラベル名にする  ほかの数値はそのまま
+    #[test]
+    fn labels() {
+        let mut labels = Labels::new("L");
+        labels.add_auto(0x8000);
+        let inst = |mnemonic, operands| Instruction{ address: 0, bytes: Vec::new(), mnemonic, operands, undocumented: false };
+        let jp = inst(Mnemonic::Jp, vec![Operand::Condition(Condition::Z), Operand::Address(0x8000)]);
+        assert_eq!(format_instruction(&jp, Some(&labels), Syntax::Listing), "JP    Z,L8000");
+        assert_eq!(format_instruction(&jp, None, Syntax::Listing), "JP    Z,8000H");
+        let ld = inst(Mnemonic::Ld, vec![Operand::RegPair(RegPair::HL), Operand::Imm16(0x8000)]);
+        assert_eq!(format_instruction(&ld, Some(&labels), Syntax::Listing), "LD    HL,8000H");
+    }
+
+    // ソースではインデックスのずれに D をつけない
+    #[test]
+    fn source_syntax() {
+        assert_eq!(format_operand(&Operand::Indexed(RegPair::IX, 5), None, Syntax::Source), "(IX+5)");
+        assert_eq!(format_operand(&Operand::Indexed(RegPair::IY, -128), None, Syntax::Source), "(IY-128)");
+        assert_eq!(format_operand(&Operand::Indexed(RegPair::IY, -128), None, Syntax::Listing), "(IY-128D)");
+    }
+}
@@ -0,0 +1,160 @@
+/*   Z80 Disassembler
+     name is instruction.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+// **********************************************
+//      レジスタ
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reg {
+    A, B, C, D, E, H, L,
+    F,                  // IN F,(C) でだけ使う
+    I, R,
+    IXH, IXL, IYH, IYL, // 未定義命令で使う
+}
+
+// **********************************************
+//      レジスタペア
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RegPair {
+    BC, DE, HL, SP, AF,
+    AFAlt,              // AF'
+    IX, IY,
+}
+
+// **********************************************
+//      条件
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Condition {
+    NZ, Z, NC, C, PO, PE, P, M,
+}
+
+// **********************************************
+//      命令の種類
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    Nop, Halt, Di, Ei, Im,
+    Ld, Push, Pop, Ex, Exx,
+    Ldi, Ldir, Ldd, Lddr, Cpi, Cpir, Cpd, Cpdr,
+    Add, Adc, Sub, Sbc, And, Xor, Or, Cp, Inc, Dec,
+    Daa, Cpl, Neg, Ccf, Scf,
+    Rlca, Rla, Rrca, Rra, Rlc, Rl, Rrc, Rr, Sla, Sra, Sll, Srl, Rld, Rrd,
+    Bit, Set, Res,
+    Jp, Jr, Djnz, Call, Ret, Reti, Retn, Rst,
+    In, Ini, Inir, Ind, Indr, Out, Outi, Otir, Outd, Otdr,
+}
+
+// **********************************************
+//      オペランド
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Reg(Reg),                   // A
+    RegPair(RegPair),           // HL
+    Indirect(RegPair),          // (HL)
+    Indexed(RegPair, i8),       // (IX+d)
+    Imm8(u8),                   // n
+    Imm16(u16),                 // nn
+    Address(u16),               // JP / CALL / JR / DJNZ の飛び先
+    Memory(u16),                // (nn)
+    Port(u8),                   // (n)
+    PortC,                      // (C)
+    Condition(Condition),       // NZ
+    Bit(u8),                    // BIT / SET / RES のビット番号
+    InterruptMode(u8),          // IM の番号
+    Restart(u8),                // RST の飛び先
+}
+
+// **********************************************
+//      逆アセンブルした１命令
+// **********************************************
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,           // 命令のアドレス
+    pub bytes: Vec<u8>,         // 命令のバイト列
+    pub mnemonic: Mnemonic,     // 命令の種類
+    pub operands: Vec<Operand>, // オペランド
+    pub undocumented: bool,     // 未定義命令なら true
+}
+
+impl Instruction {
+    // 命令のバイト数
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // 次の命令のアドレス
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    // 条件つきの命令か？ (DJNZ も条件つきとして扱う)
+    pub fn is_conditional(&self) -> bool {
+        self.mnemonic == Mnemonic::Djnz
+            || self.operands.iter().any(|o| matches!(o, Operand::Condition(_)))
+    }
+
+    // CALL / RST か？
+    pub fn is_call(&self) -> bool {
+        matches!(self.mnemonic, Mnemonic::Call | Mnemonic::Rst)
+    }
+
+    // JP / JR / DJNZ か？ (JP (HL) も含む)
+    pub fn is_jump(&self) -> bool {
+        matches!(self.mnemonic, Mnemonic::Jp | Mnemonic::Jr | Mnemonic::Djnz)
+    }
+
+    // RET / RETI / RETN か？
+    pub fn is_return(&self) -> bool {
+        matches!(self.mnemonic, Mnemonic::Ret | Mnemonic::Reti | Mnemonic::Retn)
+    }
+
+    // **********************************************
+    //      飛び先のアドレス (JP (HL) のように分からない時は None)
+    // **********************************************
+    pub fn target(&self) -> Option<u16> {
+        if !self.is_jump() && !self.is_call() {
+            return None;
+        }
+        self.operands.iter().find_map(|o| match o {
+            Operand::Address(a) => Some(*a),
+            Operand::Restart(n) => Some(*n as u16),
+            _ => None,
+        })
+    }
+
+    // **********************************************
+    //      命令の中にあるメモリのアドレス LD A,(nn) など
+    // **********************************************
+    pub fn memory_reference(&self) -> Option<u16> {
+        self.operands.iter().find_map(|o| match o {
+            Operand::Memory(a) => Some(*a),
+            _ => None,
+        })
+    }
+}
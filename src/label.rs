@@ -0,0 +1,214 @@
+/*   Z80 Disassembler
+     name is label.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::Operand;
+use crate::line::Line;
+
+// **********************************************
+//      機種で決まっているアドレスの種類
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Code,           // ルーチン  JP / CALL の飛び先だけ名前にする
+    Data,           // 変数や領域  LD A,(nn) と、システムコールに渡す LD DE,nn も名前にする
+}
+
+// **********************************************
+//      ラベル
+// **********************************************
+#[derive(Clone, Debug, Default)]
+pub struct Labels {
+    prefix: String,                 // 自動でつけるラベルの頭の文字  L1234 の L
+    names: BTreeMap<u16, String>,   // アドレス -> ラベル名
+    symbols: BTreeMap<u16, (String, SymbolKind)>,  // 逆アセンブルした範囲の外の名前 (EQU で定義する)
+    arguments: BTreeSet<u16>,       // nn を名前にする LD DE,nn などのアドレス (ほかの nn は数値のまま)
+}
+
+impl Labels {
+    pub fn new(prefix: &str) -> Self {
+        Labels{ prefix: prefix.to_string(), names: BTreeMap::new(), symbols: BTreeMap::new(), arguments: BTreeSet::new() }
+    }
+
+    // **********************************************
+    //      JP / JR / DJNZ / CALL / RST の飛び先と、アドレスの表の中身にラベルをつける
+    //      行の先頭になっているアドレスだけが対象
+    // **********************************************
+    pub fn collect(&mut self, lines: &[Line]) {
+        let starts: BTreeSet<u16> = lines.iter().map(|l| l.address()).collect();
+        for line in lines {
+            for target in line.references() {
+                if starts.contains(&target) {
+                    self.add_auto(target);
+                }
+            }
+        }
+    }
+
+    // **********************************************
+    //      機種で決まっているアドレスのうち、参照されているものに名前をつける
+    //      ルーチンは飛び先かアドレスの表にあるもの、変数は (nn) と arguments の nn にあるものだけ
+    //      行の先頭ならふつうのラベルにして、そうでなければ EQU にする
+    //      arguments は、nn をアドレスとして名前にする命令 (BDOS に渡す LD DE,nn など)
+    // **********************************************
+    pub fn collect_symbols(&mut self, symbols: &[(u16, &str, SymbolKind)], arguments: &BTreeSet<u16>, lines: &[Line]) {
+        let starts: BTreeSet<u16> = lines.iter().map(|l| l.address()).collect();
+        let mut targets: BTreeSet<u16> = BTreeSet::new();   // JP / CALL の飛び先と、アドレスの表
+        let mut values: BTreeSet<u16> = BTreeSet::new();    // LD A,(nn) と arguments の LD DE,nn の nn
+        for line in lines {
+            targets.extend(line.references());
+            if let Some(inst) = line.instruction() {
+                let argument = arguments.contains(&inst.address);
+                values.extend(inst.operands.iter().filter_map(|o| match o {
+                    Operand::Memory(n)              => Some(*n),
+                    Operand::Imm16(n) if argument   => Some(*n),
+                    _                               => None,
+                }));
+            }
+        }
+        self.arguments.extend(arguments);
+        for (address, name, kind) in symbols {
+            let used = match kind {
+                SymbolKind::Code => targets.contains(address),
+                SymbolKind::Data => values.contains(address),
+            };
+            if !used {
+                continue;
+            }
+            if starts.contains(address) {
+                self.names.entry(*address).or_insert(name.to_string());
+            }
+            self.symbols.insert(*address, (name.to_string(), *kind));
+        }
+    }
+
+    // 自動でラベルをつける  既に名前があれば何もしない
+    pub fn add_auto(&mut self, address: u16) {
+        let name = format!("{}{:04X}", self.prefix, address);
+        self.names.entry(address).or_insert(name);
+    }
+
+    // 名前を指定してラベルをつける
+    pub fn add(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    // アドレスのラベル名 (なければ範囲の外の名前)
+    pub fn get(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).or(self.symbols.get(&address).map(|(n, _)| n)).map(|s| s.as_str())
+    }
+
+    // LD A,(nn) の nn につける名前  (変数や領域の名前だけ  行の先頭ならそのラベル)
+    pub fn value(&self, address: u16) -> Option<&str> {
+        match self.symbols.get(&address) {
+            Some((_, SymbolKind::Data)) => self.get(address),
+            _ => None,
+        }
+    }
+
+    // inst_address の命令の LD DE,nn の nn につける名前  (システムコールに渡すものだけ)
+    pub fn argument(&self, inst_address: u16, value: u16) -> Option<&str> {
+        if self.arguments.contains(&inst_address) {
+            self.value(value)
+        } else {
+            None
+        }
+    }
+
+    // 名前からアドレスを探す
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.iter().chain(self.symbols()).find(|(_, n)| *n == name).map(|(a, _)| a)
+    }
+
+    // 自動でつけた名前 (L1234) なら true
+    pub fn is_auto(&self, address: u16) -> bool {
+        self.get(address) == Some(format!("{}{:04X}", self.prefix, address).as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.symbols.is_empty()
+    }
+
+    // アドレス順にすべてのラベル
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(a, n)| (*a, n.as_str()))
+    }
+
+    // アドレス順に範囲の外の名前 (EQU で定義するもの  行の先頭でラベルになったものは除く)
+    pub fn symbols(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.symbols.iter()
+                    .filter(|(a, _)| !self.names.contains_key(a))
+                    .map(|(a, (n, _))| (*a, n.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble_one;
+
+    // bytes を address から並べた命令
+    fn lines(bytes: &[u8], address: u16) -> Vec<Line> {
+        let mut list = Vec::new();
+        let mut offset = 0;
+        while let Some(inst) = disassemble_one(&bytes[offset..], address + offset as u16) {
+            offset += inst.size();
+            list.push(Line::Code(inst));
+        }
+        list
+    }
+
+    #[test]
+    fn branch_targets() {
+        let list = lines(&[
+            0x18, 0x02,             // 8000  JR    8004H
+            0x10, 0xfc,             // 8002  DJNZ  8000H
+            0xcd, 0x0b, 0x80,       // 8004  CALL  800BH
+            0xc3, 0x05, 0x80,       // 8007  JP    8005H        命令の途中
+            0xff,                   // 800A  RST   38H          範囲の外
+            0xc2, 0x00, 0x90,       // 800B  JP    NZ,9000H     範囲の外
+        ], 0x8000);
+        let mut labels = Labels::new("L");
+        labels.collect(&list);
+        let names: Vec<(u16, &str)> = labels.iter().collect();
+        assert_eq!(names, [(0x8000, "L8000"), (0x8004, "L8004"), (0x800b, "L800B")]);
+    }
+
+    // 名前を指定したラベルは、自動のラベルで上書きしない
+    #[test]
+    fn named_labels_win() {
+        let mut labels = Labels::new("SUB_");
+        labels.add(0x8000, "START");
+        labels.add_auto(0x8000);
+        labels.add_auto(0x8010);
+        assert_eq!(labels.get(0x8000), Some("START"));
+        assert_eq!(labels.get(0x8010), Some("SUB_8010"));
+        labels.add(0x8010, "LOOP");
+        assert_eq!(labels.get(0x8010), Some("LOOP"));
+        assert_eq!(labels.get(0x8020), None);
+    }
+}
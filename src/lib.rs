@@ -0,0 +1,133 @@
+/*   Z80 Disassembler
+     name is lib.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+pub mod assemble;
+pub mod charcode;
+pub mod control;
+pub mod data;
+mod disassemble;
+pub mod encode;
+pub mod flow;
+pub mod format;
+pub mod instruction;
+pub mod label;
+pub mod line;
+pub mod loader;
+pub mod source;
+pub mod system;
+pub mod verify;
+
+pub use crate::assemble::{assemble, AsmError, Program};
+pub use crate::charcode::Charset;
+pub use crate::control::Control;
+pub use crate::data::DataOptions;
+pub use crate::disassemble::Disassemble;
+pub use crate::encode::encode;
+pub use crate::instruction::{Condition, Instruction, Mnemonic, Operand, Reg, RegPair};
+pub use crate::label::{Labels, SymbolKind};
+pub use crate::line::{DataKind, Line};
+pub use crate::loader::{Image, LoadError, Segment};
+pub use crate::source::Dialect;
+pub use crate::system::System;
+pub use crate::verify::Difference;
+
+// **********************************************
+//      １命令だけ逆アセンブルする
+//      data の先頭を address に置かれた命令として解釈する
+//      データが足りない時は None を返す
+// **********************************************
+pub fn disassemble_one(data: &[u8], address: u16) -> Option<Instruction> {
+    let len = data.len().min(4);                // Z80 の命令は最長４バイト
+    let mut dasm = Disassemble::new(data[..len].to_vec(), address.into());
+    dasm.decode(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ニーモニックとバイト数と、未定義命令かどうか
+    fn decode(bytes: &[u8]) -> (String, usize, bool) {
+        let result = disassemble_one(bytes, 0x1000).unwrap();
+        (result.to_string(), result.size(), result.undocumented)
+    }
+
+    #[test]
+    fn each_prefix_page() {
+        let cases: &[(&[u8], &str, bool)] = &[
+            // ----- プリフィクスなし -----
+            (&[0x00],                   "NOP",              false),
+            (&[0x39],                   "ADD   HL,SP",      false),
+            (&[0x3e, 0x12],             "LD    A,12H",      false),
+            // ----- CB -----
+            (&[0xcb, 0x7e],             "BIT   7,(HL)",     false),
+            (&[0xcb, 0x30],             "SLL   B",          true),
+            // ----- ED -----
+            (&[0xed, 0xb0],             "LDIR",             false),
+            (&[0xed, 0x44],             "NEG",              false),
+            (&[0xed, 0x4c],             "NEG",              true),
+            (&[0xed, 0x70],             "IN    F,(C)",      true),
+            (&[0xed, 0x71],             "OUT   (C),00H",    true),
+            (&[0xed, 0x63, 0x34, 0x12], "LD    (1234H),HL", true),
+            // ----- DD / FD -----
+            (&[0xdd, 0x7e, 0x05],       "LD    A,(IX+5D)",  false),
+            (&[0xfd, 0x96, 0xfb],       "SUB   (IY-5D)",    false),
+            (&[0xdd, 0x94],             "SUB   IXH",        true),
+            (&[0xfd, 0x26, 0x12],       "LD    IYH,12H",    true),
+            (&[0xdd, 0x00],             "NOP",              true),     // HL を使わない命令のプリフィクスは無視される
+            // ----- DD CB / FD CB -----
+            (&[0xdd, 0xcb, 0x05, 0x46], "BIT   0,(IX+5D)",  false),
+            (&[0xfd, 0xcb, 0xfe, 0x06], "RLC   (IY-2D)",    false),
+            (&[0xdd, 0xcb, 0x80, 0x86], "RES   0,(IX-128D)", false),
+            (&[0xdd, 0xcb, 0x05, 0x36], "SLL   (IX+5D)",    true),
+            (&[0xdd, 0xcb, 0x05, 0x00], "RLC   (IX+5D),B",  true),     // 結果をレジスタにもコピーする
+            (&[0xfd, 0xcb, 0x05, 0xc7], "SET   0,(IY+5D),A", true),
+        ];
+        for (bytes, text, undocumented) in cases {
+            assert_eq!(decode(bytes), (text.to_string(), bytes.len(), *undocumented), "{:02X?}", bytes);
+        }
+    }
+
+    // プリフィクスが続いたら、前のものは NOP
+    #[test]
+    fn repeated_prefix() {
+        assert_eq!(decode(&[0xdd, 0xfd, 0x21, 0x34, 0x12]), (String::from("NOP"), 1, true));
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(disassemble_one(&[0xc3, 0x00], 0).is_none());
+        assert!(disassemble_one(&[0xed, 0x43, 0x34], 0).is_none());
+        assert!(disassemble_one(&[0xdd, 0xcb, 0x05], 0).is_none());
+        assert!(disassemble_one(&[], 0).is_none());
+    }
+
+    #[test]
+    fn address() {
+        let result = disassemble_one(&[0xc3, 0x00, 0x80, 0xff], 0x4000).unwrap();
+        assert_eq!((result.address, result.size()), (0x4000, 3));
+    }
+}
@@ -0,0 +1,98 @@
+/*   Z80 Disassembler
+     name is line.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::instruction::Instruction;
+
+// **********************************************
+//      データの種類
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DataKind {
+    Bytes,          // DB
+    Words,          // DW
+    Pointers,       // DW でアドレスを指す (ラベルにする)
+    Text,           // 文字列
+    Fill,           // 同じバイトの繰り返し (DS)
+}
+
+// **********************************************
+//      逆アセンブル結果の１行
+// **********************************************
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Code(Instruction),                                      // 命令
+    Data { address: u16, bytes: Vec<u8>, kind: DataKind }, // データ
+    Skip { address: u16, size: usize },                    // 出力しないところ
+}
+
+impl Line {
+    // 行の先頭アドレス
+    pub fn address(&self) -> u16 {
+        match self {
+            Line::Code(inst)           => inst.address,
+            Line::Data { address, .. } => *address,
+            Line::Skip { address, .. } => *address,
+        }
+    }
+
+    // 行のバイト列  (Skip の時は空)
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Line::Code(inst)         => &inst.bytes,
+            Line::Data { bytes, .. } => bytes,
+            Line::Skip { .. }        => &[],
+        }
+    }
+
+    // 行のバイト数
+    pub fn size(&self) -> usize {
+        match self {
+            Line::Skip { size, .. } => *size,
+            _                       => self.bytes().len(),
+        }
+    }
+
+    // 命令なら Some
+    pub fn instruction(&self) -> Option<&Instruction> {
+        match self {
+            Line::Code(inst) => Some(inst),
+            _                => None,
+        }
+    }
+
+    // **********************************************
+    //      この行が指しているアドレス (ラベルをつける候補)
+    //      命令の飛び先と、アドレスの表の中身
+    // **********************************************
+    pub fn references(&self) -> Vec<u16> {
+        match self {
+            Line::Code(inst) => inst.target().into_iter().collect(),
+            Line::Data { bytes, kind: DataKind::Pointers, .. } =>
+                bytes.chunks_exact(2).map(|w| u16::from_le_bytes([w[0], w[1]])).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
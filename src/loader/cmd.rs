@@ -0,0 +1,110 @@
+/*   Z80 Disassembler
+     name is loader/cmd.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+
+use super::{Image, LoadError};
+
+// **********************************************
+//      TRS-80 の /CMD (ロードモジュール)
+//
+//      種類(1) 長さ(1) データ  のレコードが並ぶ
+//          01H  ロードブロック  アドレス(2) + データ
+//               長さはアドレスを含む  0 / 1 / 2 は 256 / 257 / 258
+//          02H  実行開始アドレス(2)  ここで終わり
+//          ほか (05H モジュール名  1FH コピーライト など) は読み飛ばす
+// **********************************************
+
+const LOAD_BLOCK: u8 = 0x01;
+const TRANSFER: u8 = 0x02;
+
+pub fn parse(data: &[u8]) -> Result<Image, LoadError> {
+    let mut memory: BTreeMap<u16, u8> = BTreeMap::new();
+    let mut entry_points = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let kind = data[pos];
+        let Some(length) = data.get(pos + 1).map(|n| *n as usize) else {
+            return Err(LoadError::Format(format!("CMD: record {:02X}H at {:X}H is truncated", kind, pos)));
+        };
+        let length = if kind == LOAD_BLOCK && length < 3 { length + 256 } else { length };
+        let Some(body) = data.get(pos + 2..pos + 2 + length) else {
+            return Err(LoadError::Format(format!("CMD: record {:02X}H at {:X}H is truncated", kind, pos)));
+        };
+        match kind {
+            LOAD_BLOCK => {
+                let address = u16::from_le_bytes([body[0], body[1]]);
+                for (i, byte) in body[2..].iter().enumerate() {
+                    memory.insert(address.wrapping_add(i as u16), *byte);
+                }
+            },
+            TRANSFER => {
+                if let [low, high, ..] = body {
+                    entry_points.push(u16::from_le_bytes([*low, *high]));
+                }
+                break;
+            },
+            _ => {},
+        }
+        pos += 2 + length;
+    }
+    if memory.is_empty() {
+        return Err(LoadError::Format(String::from("CMD: no load block")));
+    }
+    let mut image = Image::from_memory(&memory);
+    image.entry_points = entry_points;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+    use crate::loader::tests::format_error;
+
+    #[test]
+    fn records() {
+        let mut data = vec![0x05, 0x04, b'T', b'E', b'S', b'T'];        // モジュール名
+        data.extend([LOAD_BLOCK, 0x05, 0x00, 0x70, 0x3e, 0x01, 0xc9]);
+        data.extend([LOAD_BLOCK, 0x02, 0x00, 0x80]);                    // 長さ 2 は 258 (データ 256 バイト)
+        data.extend([0xaa; 256]);
+        data.extend([TRANSFER, 0x02, 0x00, 0x70]);
+        data.extend([LOAD_BLOCK, 0x03, 0x00, 0x90, 0xff]);              // 実行開始アドレスの後ろは読まない
+        let image = parse(&data).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x7000, data: vec![0x3e, 0x01, 0xc9] },
+                                    Segment{ address: 0x8000, data: vec![0xaa; 256] }]);
+        assert_eq!(image.entry_points, [0x7000]);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = [LOAD_BLOCK, 0x05, 0x00, 0x70, 0x3e, 0x01, 0xc9, TRANSFER, 0x02, 0x00, 0x70];
+        assert_eq!(format_error(parse(&data[..6])), "CMD: record 01H at 0H is truncated");
+        assert_eq!(format_error(parse(&data[..8])), "CMD: record 02H at 7H is truncated");
+        assert_eq!(format_error(parse(&data[..10])), "CMD: record 02H at 7H is truncated");
+        assert_eq!(format_error(parse(&[0x05, 0x01, b'X'])), "CMD: no load block");
+    }
+}
@@ -0,0 +1,238 @@
+/*   Z80 Disassembler
+     name is loader/cmt.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::p6::basic_program;
+use super::{file_name, Block, BlockKind, LoadError};
+
+// **********************************************
+//      PC-8001 / PC-8801 のテープイメージ (.cmt)
+//
+//      テープから読んだバイト列がそのまま並んでいる
+//          BASIC   D3H x10 + ファイル名 6 バイト + プログラム
+//          機械語  (モニタの W コマンド)
+//              3AH + アドレス(2、上位が先) + チェックサム
+//              3AH + 長さ(1) + データ + チェックサム  を繰り返す
+//              3AH 00H 00H  で終わり
+//          チェックサムは、3AH のうしろから足して 0 になる値
+//      レコードの間のほかのバイトは読み飛ばす
+// **********************************************
+
+const MARK: u8 = 0x3a;
+const BASIC: u8 = 0xd3;
+const HEADER_SIZE: usize = 10;
+const NAME_SIZE: usize = 6;
+
+pub fn blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Block> = None;     // 読んでいる機械語のファイル
+    let mut errors: Vec<u16> = Vec::new();     // チェックサムが合わなかったレコードのアドレス
+    let mut pos = 0;
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    let truncated = |pos: usize| LoadError::Format(format!("CMT: record at {:X}H is truncated", pos));
+
+    while pos < data.len() {
+        // ----- BASIC -----
+        if current.is_none() && data.get(pos..pos + HEADER_SIZE).is_some_and(|h| h.iter().all(|b| *b == BASIC)) {
+            pos += HEADER_SIZE;
+            let name = file_name(data.get(pos..pos + NAME_SIZE).ok_or_else(|| truncated(pos))?, Charset::Pc6001);
+            pos += NAME_SIZE;
+            let (size, address) = basic_program(&data[pos..])
+                .ok_or_else(|| LoadError::Format(format!("BASIC program {} is truncated", name)))?;
+            blocks.push(Block{ name, kind: BlockKind::Basic, address, exec: None, data: data[pos..pos + size].to_vec(), note: String::new() });
+            pos += size;
+            continue;
+        }
+        if data[pos] != MARK {
+            pos += 1;
+            continue;
+        }
+
+        match current.as_mut() {
+            // ----- アドレスのレコード -----
+            None => {
+                let record = data.get(pos + 1..pos + 4).ok_or_else(|| truncated(pos))?;
+                let address = u16::from_be_bytes([record[0], record[1]]);
+                if sum(record) != 0 {
+                    errors.push(address);
+                }
+                current = Some(Block{ name: String::new(), kind: BlockKind::Machine, address: Some(address), exec: None,
+                                      data: Vec::new(), note: String::new() });
+                pos += 4;
+            },
+            // ----- データのレコード -----
+            Some(block) => {
+                let length = *data.get(pos + 1).ok_or_else(|| truncated(pos))? as usize;
+                if length == 0 {                // 終わり
+                    block.note = checksum_note(&std::mem::take(&mut errors));
+                    blocks.extend(current.take());
+                    pos += 3;
+                    continue;
+                }
+                let record = data.get(pos + 1..pos + 3 + length).ok_or_else(|| truncated(pos))?;
+                if sum(record) != 0 {
+                    errors.push(block.address.unwrap_or(0).wrapping_add(block.data.len() as u16));
+                }
+                block.data.extend(&record[1..1 + length]);
+                pos += 3 + length;
+            },
+        }
+    }
+    if let Some(mut block) = current {
+        let note = checksum_note(&errors);
+        block.note = if note.is_empty() { String::from("no end record") } else { format!("{}, no end record", note) };
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+// チェックサムが合わなかったレコードをすべて並べる  checksum error at 1000H, 1010H
+fn checksum_note(errors: &[u16]) -> String {
+    if errors.is_empty() {
+        return String::new();
+    }
+    let addresses: Vec<String> = errors.iter().map(|a| format!("{:04X}H", a)).collect();
+    format!("checksum error at {}", addresses.join(", "))
+}
+
+// **********************************************
+//      T88  "PC-8801 Tape Image(T88)" 00H + タグの並び
+//          タグ  ID(2) 長さ(2) 中身
+//              0000H 終わり  0101H データ (開始時間(4) 長さ(4) バイト数(2) 種類(2) データ)
+//              ほか (版数 / 無音 / スペース / マーク) は読み飛ばす
+//      データのタグをつなげて .cmt と同じに読む
+// **********************************************
+
+pub const T88_ID: &[u8] = b"PC-8801 Tape Image(T88)\0";
+const TAG_END: u16 = 0x0000;
+const TAG_DATA: u16 = 0x0101;
+const DATA_HEADER: usize = 12;
+
+pub fn t88_blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    if !data.starts_with(T88_ID) {
+        return Err(LoadError::Format(String::from("T88: no header")));
+    }
+    let mut stream = Vec::new();
+    let mut pos = T88_ID.len();
+    while pos + 4 <= data.len() {
+        let id = u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let length = u16::from_le_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if id == TAG_END {
+            break;
+        }
+        let Some(body) = data.get(pos + 4..pos + 4 + length) else {
+            return Err(LoadError::Format(format!("T88: tag {:04X}H at {:X}H is truncated", id, pos)));
+        };
+        if id == TAG_DATA && body.len() >= DATA_HEADER {
+            stream.extend(&body[DATA_HEADER..]);
+        }
+        pos += 4 + length;
+    }
+    blocks(&stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    // チェックサムをつけたレコード
+    fn record(body: &[u8]) -> Vec<u8> {
+        let mut record = vec![MARK];
+        record.extend(body);
+        record.push(body.iter().fold(0u8, |s, b| s.wrapping_sub(*b)));
+        record
+    }
+
+    // C000H から 3 バイトずつ 2 レコードの機械語
+    fn machine() -> Vec<u8> {
+        let mut data = vec![0x00; 4];                       // レコードの前のバイトは読み飛ばす
+        data.extend(record(&[0xc0, 0x00]));
+        data.extend(record(&[0x03, 0x3e, 0x01, 0xc9]));
+        data.extend(record(&[0x03, 0x00, 0x00, 0x00]));
+        data.extend([MARK, 0x00, 0x00]);
+        data
+    }
+
+    #[test]
+    fn machine_and_basic() {
+        let mut data = machine();
+        data.extend([BASIC; HEADER_SIZE]);
+        data.extend(b"HELLO ");
+        data.extend([0x07, 0x80, 0x0a, 0x00, 0x91, 0x00, 0x00, 0x00]);     // 8001  10 PRINT
+        let blocks = blocks(&data).unwrap();
+        assert_eq!((blocks[0].kind, blocks[0].address, blocks[0].note.as_str()), (BlockKind::Machine, Some(0xc000), ""));
+        assert_eq!(blocks[0].data, [0x3e, 0x01, 0xc9, 0x00, 0x00, 0x00]);
+        assert_eq!((blocks[1].name.as_str(), blocks[1].kind, blocks[1].address), ("HELLO", BlockKind::Basic, Some(0x8001)));
+    }
+
+    // チェックサムの合わないレコードはすべて並べる
+    #[test]
+    fn bad_checksums() {
+        let mut data = machine();
+        data[4 + 4 + 5] ^= 0x01;        // C000H のレコードのチェックサム
+        data[4 + 4 + 6 + 2] ^= 0x01;    // C003H のレコードのデータ
+        let blocks = blocks(&data).unwrap();
+        assert_eq!(blocks[0].note, "checksum error at C000H, C003H");
+        assert_eq!(blocks[0].data, [0x3e, 0x01, 0xc9, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = machine();
+        let end = data.len() - 3;
+        assert_eq!(blocks(&data[..end]).unwrap()[0].note, "no end record");
+        assert_eq!(format_error(blocks(&data[..end - 1])), format!("CMT: record at {:X}H is truncated", end - 6));
+        assert_eq!(format_error(blocks(&data[..6])), "CMT: record at 4H is truncated");
+        let mut basic = vec![BASIC; HEADER_SIZE];
+        basic.extend(b"HEL");
+        assert_eq!(format_error(blocks(&basic)), "CMT: record at AH is truncated");
+        basic.extend(b"LO \x07\x80");
+        assert_eq!(format_error(blocks(&basic)), "BASIC program HELLO is truncated");
+    }
+
+    fn t88(stream: &[u8]) -> Vec<u8> {
+        let mut data = T88_ID.to_vec();
+        data.extend([0x01, 0x00, 0x02, 0x00, 0x00, 0x01]);                 // 版数
+        data.extend(TAG_DATA.to_le_bytes());
+        data.extend(((DATA_HEADER + stream.len()) as u16).to_le_bytes());
+        data.extend([0x00; DATA_HEADER]);
+        data.extend(stream);
+        data.extend([0x00; 4]);                                             // 終わり
+        data
+    }
+
+    #[test]
+    fn t88_tags() {
+        let blocks = t88_blocks(&t88(&machine())).unwrap();
+        assert_eq!((blocks.len(), blocks[0].address), (1, Some(0xc000)));
+        let data = t88(&machine());
+        assert!(matches!(t88_blocks(&data[1..]), Err(LoadError::Format(m)) if m == "T88: no header"));
+        let tag = T88_ID.len() + 6;
+        assert_eq!(format_error(t88_blocks(&data[..tag + 10])), format!("T88: tag 0101H at {:X}H is truncated", tag));
+    }
+}
@@ -0,0 +1,340 @@
+/*   Z80 Disassembler
+     name is loader/cpc.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+
+use crate::charcode::Charset;
+
+use super::disk::{Disk, Sector};
+use super::{file_name, Block, BlockKind, Image, LoadError, LoadOptions, Select};
+
+// **********************************************
+//      Amstrad CPC の AMSDOS ヘッダ (128 バイト)
+//          +00H ユーザー  +01H 名前(8) 拡張子(3)  +12H 種類 (0 BASIC  1 保護した BASIC  2 バイナリ)
+//          +15H 読み込むアドレス  +18H 長さ  +1AH 実行アドレス  +40H 長さ(3)
+//          +43H チェックサム (+00H-+42H を足したもの)
+// **********************************************
+
+const AMSDOS_HEADER: usize = 128;
+
+// AMSDOS のヘッダがついているか？
+pub fn has_header(data: &[u8]) -> bool {
+    let Some(header) = data.get(..AMSDOS_HEADER) else {
+        return false;
+    };
+    let sum = header[..0x43].iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+    sum != 0 && sum == u16::from_le_bytes([header[0x43], header[0x44]])
+}
+
+// **********************************************
+//      AMSDOS のファイルを、ヘッダを取ったブロックにする
+//      ヘッダがなければ (アスキーのファイルなど) アドレスのわからないデータ
+// **********************************************
+pub fn amsdos(name: String, data: &[u8]) -> Block {
+    if !has_header(data) {
+        return Block{ name, kind: BlockKind::Data, address: None, exec: None, data: data.to_vec(), note: String::new() };
+    }
+    let word = |p: usize| u16::from_le_bytes([data[p], data[p + 1]]);
+    let length = (word(0x40) as usize | (data[0x42] as usize) << 16).min(data.len() - AMSDOS_HEADER);
+    let body = data[AMSDOS_HEADER..AMSDOS_HEADER + length].to_vec();
+    let name = if name.is_empty() { header_name(data) } else { name };
+    match data[0x12] & 0x0e {
+        0 => Block{ name, kind: BlockKind::Basic, address: None, exec: None, data: body, note: String::new() },
+        2 => Block{ name, kind: BlockKind::Machine, address: Some(word(0x15)), exec: Some(word(0x1a)),
+                    data: body, note: String::new() },
+        _ => Block{ name, kind: BlockKind::Data, address: Some(word(0x15)), exec: None, data: body, note: String::new() },
+    }
+}
+
+// 名前.拡張子
+fn header_name(entry: &[u8]) -> String {
+    let bytes: Vec<u8> = entry[1..12].iter().map(|b| b & 0x7f).collect();    // 上のビットは属性
+    let name = file_name(&bytes[..8], Charset::Ascii);
+    let extension = file_name(&bytes[8..], Charset::Ascii);
+    if extension.is_empty() { name } else { format!("{}.{}", name, extension) }
+}
+
+// **********************************************
+//      CPC のディスクイメージ (.dsk)
+//
+//      ディスクの情報 (256 バイト)
+//          "MV - CPC" (標準) / "EXTENDED CPC DSK File" (拡張)
+//          +30H トラック数  +31H 面の数  +32H トラックの大きさ (標準)  +34H トラックの大きさの表 (拡張、x256)
+//      トラックの情報 (256 バイト)  "Track-Info"
+//          +14H セクタの大きさ N  +15H セクタ数  +18H セクタの情報 8 バイトずつ
+//              C H R N ST1 ST2 データの長さ(2、拡張だけ)
+//      そのうしろにセクタのデータ
+//      トラック番号は  シリンダ x 面の数 + 面
+//      面の数と、ディスクの中身を返す
+// **********************************************
+
+const DISK_INFO: usize = 0x100;
+
+fn read(data: &[u8]) -> Result<(usize, Disk), LoadError> {
+    let error = |message: String| LoadError::Format(format!("DSK: {}", message));
+    let extended = data.starts_with(b"EXTENDED CPC DSK File");
+    if !extended && !data.starts_with(b"MV - CPC") {
+        return Err(error(String::from("no disk information block")));
+    }
+    if data.len() < DISK_INFO {
+        return Err(error(String::from("disk information is truncated")));
+    }
+    let sides = data[0x31].max(1) as usize;
+    let count = data[0x30] as usize * sides;
+    let mut tracks = Vec::new();
+    let mut pos = DISK_INFO;
+    for i in 0..count {
+        let size = if extended {
+            data.get(0x34 + i).copied().unwrap_or(0) as usize * 256
+        } else {
+            u16::from_le_bytes([data[0x32], data[0x33]]) as usize
+        };
+        if size == 0 {
+            tracks.push(Vec::new());    // フォーマットしていないトラック
+            continue;
+        }
+        let Some(track) = data.get(pos..pos + size).filter(|t| t.starts_with(b"Track-Info")) else {
+            return Err(error(format!("track {} at {:X}H is broken", i, pos)));
+        };
+        let sector_size = 128usize << (track[0x14] & 0x07);
+        let mut sectors = Vec::new();
+        let mut offset = DISK_INFO;
+        for info in track[0x18..].chunks_exact(8).take(track[0x15] as usize) {
+            let length = if extended { u16::from_le_bytes([info[6], info[7]]) as usize } else { sector_size };
+            let Some(body) = track.get(offset..offset + length) else {
+                return Err(error(format!("sector {:02X}H of track {} is truncated", info[2], i)));
+            };
+            sectors.push(Sector{ r: info[2], data: body.to_vec() });
+            offset += length;
+        }
+        tracks.push(sectors);
+        pos += size;
+    }
+    Ok((sides, Disk{ tracks }))
+}
+
+pub fn dsk(data: &[u8], options: &LoadOptions) -> Result<Image, LoadError> {
+    let (sides, disk) = read(data)?;
+    // セクタを指定した時は、ディレクトリを読まない (AMSDOS のディスクでないものもある)
+    if let Some(Select::Sectors { .. }) = options.select {
+        return disk.image(Vec::new(), options);
+    }
+    disk.image(files(&disk, sides)?, options)
+}
+
+// **********************************************
+//      AMSDOS のディレクトリ
+//
+//      最初のセクタの番号で形式が決まる
+//          C1H  DATA    (予約トラックなし)
+//          41H  SYSTEM  (予約トラック 2)
+//          01H  IBM     (予約トラック 1)
+//      1 トラック 9 セクタ (512 バイト)、ブロックは 1K (2 セクタ)  表の面 (面 0) だけを使う
+//      ディレクトリはブロック 0-1 で、32 バイトずつ
+//          ユーザー(1、E5H は消したもの) 名前(8) 拡張子(3) エクステント(1) (2) レコード数(1) ブロック(16)
+// **********************************************
+
+const SECTORS: usize = 9;
+const BLOCK_SECTORS: usize = 2;
+const DIRECTORY_BLOCKS: usize = 2;
+const RECORD: usize = 128;
+
+fn files(disk: &Disk, sides: usize) -> Result<Vec<Block>, LoadError> {
+    let Some(first) = disk.tracks.first().and_then(|t| t.iter().map(|s| s.r).min()) else {
+        return Ok(Vec::new());
+    };
+    let (base, reserved) = match first & 0xc0 {
+        0xc0 => (0xc1, 0),
+        0x40 => (0x41, 2),
+        _    => (0x01, 1),
+    };
+    let block = |n: usize| -> Result<Vec<u8>, LoadError> {
+        let mut data = Vec::new();
+        for s in n * BLOCK_SECTORS..(n + 1) * BLOCK_SECTORS {
+            let r = base + (s % SECTORS) as u8;
+            data.extend(disk.sectors((reserved + s / SECTORS) * sides, r, r)?);
+        }
+        Ok(data)
+    };
+
+    // ----- エクステントを、ファイルごとにまとめる -----
+    let mut directory = Vec::new();
+    for n in 0..DIRECTORY_BLOCKS {
+        let Ok(data) = block(n) else {
+            return Ok(Vec::new());  // ディレクトリのセクタがない  AMSDOS のディスクではない
+        };
+        directory.extend(data);
+    }
+    let mut extents: BTreeMap<(u8, String), Vec<&[u8]>> = BTreeMap::new();
+    let mut order = Vec::new();     // ディレクトリに出てきた順
+    for entry in directory.chunks_exact(32) {
+        if entry[0] > 15 {
+            continue;               // E5H は消したもの
+        }
+        let key = (entry[0], header_name(entry));
+        if !extents.contains_key(&key) {
+            order.push(key.clone());
+        }
+        extents.entry(key).or_default().push(entry);
+    }
+
+    let mut blocks = Vec::new();
+    for key in order {
+        let mut entries = extents.remove(&key).unwrap_or_default();
+        entries.sort_by_key(|e| (e[14] as usize) << 5 | e[12] as usize);   // S2 と EX の順
+        let mut data = Vec::new();
+        let mut size = 0;
+        for entry in &entries {
+            for n in entry[16..].iter().filter(|n| **n != 0) {
+                data.extend(block(*n as usize)?);
+            }
+            size += entry[15] as usize * RECORD;
+        }
+        data.truncate(size);
+        let (user, name) = key;
+        let mut file = amsdos(name, &data);
+        if user != 0 {
+            file.note = format!("user {}", user);
+        }
+        blocks.push(file);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+    use crate::loader::tests::format_error;
+
+    const CODE: [u8; 3] = [0x3e, 0x01, 0xc9];
+    const SECTOR_SIZE: usize = 512;
+    const TRACK_SIZE: usize = DISK_INFO + SECTORS * SECTOR_SIZE;
+
+    // 4000H に読み込んで 4000H から実行するバイナリ
+    fn binary() -> Vec<u8> {
+        let mut data = vec![0x00; AMSDOS_HEADER];
+        data[1..12].copy_from_slice(b"PROG    BIN");
+        data[0x12] = 2;
+        data[0x15..0x17].copy_from_slice(&0x4000u16.to_le_bytes());
+        data[0x1a..0x1c].copy_from_slice(&0x4000u16.to_le_bytes());
+        data[0x40] = CODE.len() as u8;
+        let sum = data[..0x43].iter().fold(0u16, |sum, b| sum + *b as u16);
+        data[0x43..0x45].copy_from_slice(&sum.to_le_bytes());
+        data.extend(CODE);
+        data
+    }
+
+    #[test]
+    fn amsdos_header() {
+        let block = amsdos(String::new(), &binary());
+        assert_eq!((block.name.as_str(), block.kind, block.address, block.exec), ("PROG.BIN", BlockKind::Machine, Some(0x4000), Some(0x4000)));
+        assert_eq!(block.data, CODE);
+        // 長さより短いファイルは、あるところまで
+        let block = amsdos(String::from("CUT"), &binary()[..AMSDOS_HEADER + 1]);
+        assert_eq!(block.data, CODE[..1]);
+    }
+
+    // チェックサムが合わなければヘッダではない
+    #[test]
+    fn amsdos_bad_checksum() {
+        let mut data = binary();
+        data[0x43] ^= 0x01;
+        assert!(!has_header(&data));
+        let block = amsdos(String::from("PROG.BIN"), &data);
+        assert_eq!((block.kind, block.address, block.data.len()), (BlockKind::Data, None, AMSDOS_HEADER + CODE.len()));
+        assert!(!has_header(&binary()[..AMSDOS_HEADER - 1]));
+    }
+
+    // DATA 形式の標準の .dsk  1 トラックで、ブロック 2 に PROG.BIN
+    fn disk() -> Vec<u8> {
+        let mut data = vec![0x00; DISK_INFO];
+        data[..8].copy_from_slice(b"MV - CPC");
+        data[0x30] = 1;
+        data[0x31] = 1;
+        data[0x32..0x34].copy_from_slice(&(TRACK_SIZE as u16).to_le_bytes());
+
+        let mut track = vec![0x00; DISK_INFO];
+        track[..10].copy_from_slice(b"Track-Info");
+        track[0x14] = 2;
+        track[0x15] = SECTORS as u8;
+        for s in 0..SECTORS {
+            track[0x18 + s * 8..][..4].copy_from_slice(&[0, 0, 0xc1 + s as u8, 2]);
+        }
+        let mut sectors = vec![0xe5; 4 * SECTOR_SIZE];          // ディレクトリ
+        sectors[..16].copy_from_slice(b"\x00PROG    BIN\x00\x00\x00\x02");
+        sectors[16..32].copy_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut file = binary();
+        file.resize(2 * SECTOR_SIZE, 0x1a);
+        sectors.extend(file);
+        sectors.resize(SECTORS * SECTOR_SIZE, 0xe5);
+        data.extend(track);
+        data.extend(sectors);
+        data
+    }
+
+    #[test]
+    fn dsk_files() {
+        let image = dsk(&disk(), &LoadOptions::default()).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x4000, data: CODE.to_vec() }]);
+        assert_eq!(image.entry_points, [0x4000]);
+        assert_eq!(image.blocks[0].name, "PROG.BIN");
+    }
+
+    // AMSDOS のディスクでなくても、セクタは読める
+    #[test]
+    fn dsk_sectors() {
+        let mut data = disk();
+        for s in 0..SECTORS {
+            data[DISK_INFO + 0x18 + s * 8 + 2] = 0x01 + s as u8;  // IBM 形式  ディレクトリのトラック 1 がない
+        }
+        assert!(dsk(&data, &LoadOptions::default()).unwrap_err().to_string().starts_with("no machine language program"));
+        let options = LoadOptions{ org: 0x8000, select: Some(Select::Sectors{ track: 0, first: 5, last: 5 }), ..Default::default() };
+        let image = dsk(&data, &options).unwrap();
+        assert_eq!((image.segments[0].address, &image.segments[0].data[1..12]), (0x8000, &b"PROG    BIN"[..]));
+    }
+
+    #[test]
+    fn dsk_truncated() {
+        let data = disk();
+        assert_eq!(format_error(dsk(&data[1..], &LoadOptions::default())), "DSK: no disk information block");
+        assert_eq!(format_error(dsk(&data[..DISK_INFO - 1], &LoadOptions::default())), "DSK: disk information is truncated");
+        assert_eq!(format_error(dsk(&data[..data.len() - 1], &LoadOptions::default())), "DSK: track 0 at 100H is broken");
+
+        // 拡張形式で、セクタの長さがトラックより長い
+        let mut extended = data.clone();
+        extended[..DISK_INFO].fill(0x00);
+        extended[..21].copy_from_slice(b"EXTENDED CPC DSK File");
+        extended[0x30] = 1;
+        extended[0x31] = 1;
+        extended[0x34] = (TRACK_SIZE / 256) as u8;
+        for s in 0..SECTORS {
+            let length = if s == 8 { SECTOR_SIZE + 1 } else { SECTOR_SIZE };
+            extended[DISK_INFO + 0x18 + s * 8 + 6..][..2].copy_from_slice(&(length as u16).to_le_bytes());
+        }
+        assert_eq!(format_error(dsk(&extended, &LoadOptions::default())), "DSK: sector C9H of track 0 is truncated");
+    }
+}
@@ -0,0 +1,293 @@
+/*   Z80 Disassembler
+     name is loader/d88.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::disk::{Disk, Sector};
+use super::{file_name, Block, BlockKind, Image, LoadError, LoadOptions, Select};
+
+// **********************************************
+//      D88 フロッピーディスクイメージ
+//
+//      ヘッダ (2B0H バイト)
+//          +00H ディスク名(17)  +1AH ライトプロテクト(1)  +1BH メディアの種類(1)
+//          +1CH ディスクのサイズ(4)  +20H トラックの位置の表 (4 バイト x 164)
+//      トラック  セクタが並ぶ
+//          +00H C H R N  +04H トラックのセクタ数(2)  +06H 密度(1)  +07H 削除マーク(1)
+//          +08H ステータス(1)  +0EH データのサイズ(2)  +10H データ
+//      トラック番号は表の順番 (両面なら シリンダ x 2 + 面)
+// **********************************************
+
+const HEADER_SIZE: usize = 0x2b0;
+const TRACK_TABLE: usize = 0x20;
+const MEDIA_1D: u8 = 0x30;
+const MEDIA_2D: u8 = 0x00;
+
+// **********************************************
+//      ディスクイメージを読む (複数のディスクが入っていても最初の１枚だけ)
+//      メディアの種類と、ディスクの中身を返す
+// **********************************************
+fn read(data: &[u8]) -> Result<(u8, Disk), LoadError> {
+    let error = |message: &str| LoadError::Format(format!("D88: {}", message));
+    if data.len() < HEADER_SIZE {
+        return Err(error("header is truncated"));
+    }
+    let long = |p: usize| u32::from_le_bytes([data[p], data[p + 1], data[p + 2], data[p + 3]]) as usize;
+    let size = long(0x1c).min(data.len());
+    let offsets: Vec<usize> = (TRACK_TABLE..HEADER_SIZE).step_by(4).map(long).collect();
+    // トラックの表が短いイメージもあるので、最初のトラックより前までを表にする
+    let first = offsets.iter().copied().filter(|o| *o != 0).min().unwrap_or(HEADER_SIZE);
+    if first < TRACK_TABLE {
+        return Err(error("bad track table"));
+    }
+    let count = ((first - TRACK_TABLE) / 4).min(offsets.len());
+
+    let mut tracks = Vec::new();
+    for offset in &offsets[..count] {
+        let mut sectors = Vec::new();
+        let mut pos = *offset;
+        if pos != 0 {
+            loop {
+                let Some(header) = data.get(pos..pos + 16).filter(|_| pos + 16 <= size) else {
+                    return Err(error(&format!("sector at {:X}H is truncated", pos)));
+                };
+                let total = u16::from_le_bytes([header[4], header[5]]) as usize;
+                let length = u16::from_le_bytes([header[14], header[15]]) as usize;
+                let Some(body) = data.get(pos + 16..pos + 16 + length) else {
+                    return Err(error(&format!("sector at {:X}H is truncated", pos)));
+                };
+                sectors.push(Sector{ r: header[2], data: body.to_vec() });
+                pos += 16 + length;
+                if sectors.len() >= total {
+                    break;
+                }
+            }
+        }
+        tracks.push(sectors);
+    }
+    Ok((data[0x1b], Disk{ tracks }))
+}
+
+pub fn parse(data: &[u8], options: &LoadOptions) -> Result<Image, LoadError> {
+    let (media, disk) = read(data)?;
+    // セクタを指定した時は、ファイルの一覧を作らない (IPL やディレクトリのないディスクもある)
+    if let Some(Select::Sectors { .. }) = options.select {
+        return disk.image(Vec::new(), options);
+    }
+    let mut blocks: Vec<Block> = ipl(&disk, media).into_iter().collect();
+    blocks.extend(basic_files(&disk, media)?);
+    disk.image(blocks, options)
+}
+
+// **********************************************
+//      IPL  トラック 0 セクタ 1
+//      PC-8801 (2D) は C000H に読み込まれて、C000H から実行される
+//      ほかの機種はアドレスがわからないので、-o で指定する
+//      トラック 0 セクタ 1 がなければ、IPL はない
+// **********************************************
+fn ipl(disk: &Disk, media: u8) -> Option<Block> {
+    let address = (media == MEDIA_2D).then_some(0xc000);
+    Some(Block{ name: String::from("IPL"), kind: BlockKind::Boot, address, exec: address,
+                data: disk.sector(0, 1)?.to_vec(), note: String::from("track 0 sector 1") })
+}
+
+// **********************************************
+//      N88-BASIC / N60-BASIC のディスクのファイル
+//
+//      ディレクトリのトラック (2D は 37 = 18 シリンダの裏、1D は 18)
+//          セクタ 1-12  ディレクトリ  16 バイトずつ
+//              名前(6) 拡張子(3) 属性(1) 最初のクラスタ(1)
+//              名前の先頭が FFH なら終わり、00H なら消したファイル
+//              属性  01H 機械語 (BSAVE)  80H BASIC (中間語)  ほかはアスキー
+//          セクタ 14  FAT  クラスタごとに次のクラスタ
+//              C1H-C8H なら最後のクラスタで、使っているセクタ数は下位の数
+//      クラスタはトラックの半分 (8 セクタ)  番号はトラック x 2 + 後半なら 1
+//      機械語のファイルの先頭 4 バイトは、開始アドレスと終了アドレス
+// **********************************************
+fn basic_files(disk: &Disk, media: u8) -> Result<Vec<Block>, LoadError> {
+    let directory = match media {
+        MEDIA_2D => 37,
+        MEDIA_1D => 18,
+        _        => return Ok(Vec::new()),      // ほかのディスクは、セクタを指定して読む
+    };
+    let Some(fat) = disk.sector(directory, 14) else {
+        return Ok(Vec::new());                  // BASIC のディスクではない
+    };
+    let mut files = Vec::new();
+    'directory: for r in 1..=12 {
+        let Some(sector) = disk.sector(directory, r) else {
+            break;
+        };
+        for entry in sector.chunks_exact(16) {
+            match entry[0] {
+                0xff => break 'directory,
+                0x00 => continue,
+                _    => {},
+            }
+            let mut name = file_name(&entry[..6], Charset::Ascii);
+            let extension = file_name(&entry[6..9], Charset::Ascii);
+            if !extension.is_empty() {
+                name = format!("{}.{}", name, extension);
+            }
+            let data = read_chain(disk, fat, entry[10])
+                .map_err(|e| LoadError::Format(format!("{}: {}", name, e)))?;
+            let attribute = entry[9];
+            let block = if attribute & 0x01 != 0 && data.len() >= 4 {
+                let start = u16::from_le_bytes([data[0], data[1]]);
+                let end = u16::from_le_bytes([data[2], data[3]]);
+                let size = (end.wrapping_sub(start) as usize).min(data.len() - 4);
+                Block{ name, kind: BlockKind::Machine, address: Some(start), exec: None,
+                       data: data[4..4 + size].to_vec(), note: String::new() }
+            } else {
+                let kind = if attribute & 0x80 != 0 { BlockKind::Basic } else { BlockKind::Data };
+                Block{ name, kind, address: None, exec: None, data, note: String::new() }
+            };
+            files.push(block);
+        }
+    }
+    Ok(files)
+}
+
+// FAT をたどって、ファイルの中身を読む
+fn read_chain(disk: &Disk, fat: &[u8], first: u8) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut cluster = first as usize;
+    for _ in 0..fat.len() {
+        let next = *fat.get(cluster).ok_or(format!("bad cluster {}", cluster))?;
+        let track = cluster / 2;
+        let first_sector = (cluster % 2) as u8 * 8 + 1;
+        let count = match next {
+            0xc1..=0xc8 => next - 0xc0,
+            _ if (next as usize) < fat.len() => 8,
+            _ => return Err(format!("bad FAT entry {:02X}H for cluster {}", next, cluster)),
+        };
+        data.extend(disk.sectors(track, first_sector, first_sector + count - 1).map_err(|e| e.to_string())?);
+        if next >= 0xc1 {
+            return Ok(data);
+        }
+        cluster = next as usize;
+    }
+    Err(String::from("FAT chain loops"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+    use crate::loader::tests::format_error;
+
+    const DIRECTORY: usize = 18;        // 1D のディレクトリのトラック
+    const SECTOR_SIZE: usize = 256;
+
+    type Track = Vec<(u8, Vec<u8>)>;       // セクタ番号と中身
+
+    // 1D のディスク  IPL と、クラスタ 2 (トラック 1 の前半) に機械語のファイル PROG.BIN
+    fn disk(fat_entry: u8) -> Vec<u8> {
+        let mut directory = vec![0xff; SECTOR_SIZE];
+        directory[..16].copy_from_slice(b"PROG  BIN\x01\x02\xff\xff\xff\xff\xff");
+        let mut fat = vec![0xff; SECTOR_SIZE];
+        fat[2] = fat_entry;
+        let mut program = vec![0x00; SECTOR_SIZE];
+        program[..7].copy_from_slice(&[0x00, 0x90, 0x03, 0x90, 0x3e, 0x01, 0xc9]);
+        let mut cluster: Track = (2..=8).map(|r| (r, vec![0x00; SECTOR_SIZE])).collect();
+        cluster.insert(0, (1, program));
+        let tracks: [(usize, Track); 3] = [
+            (0, vec![(1, vec![0xc9; SECTOR_SIZE])]),
+            (1, cluster),
+            (DIRECTORY, vec![(1, directory), (14, fat)]),
+        ];
+
+        let mut data = vec![0x00; HEADER_SIZE];
+        data[0x1b] = MEDIA_1D;
+        for (track, sectors) in tracks {
+            let offset = data.len() as u32;
+            data[TRACK_TABLE + track * 4..][..4].copy_from_slice(&offset.to_le_bytes());
+            for (r, body) in &sectors {
+                let mut header = [0x00; 16];
+                header[2] = *r;
+                header[3] = 1;
+                header[4..6].copy_from_slice(&(sectors.len() as u16).to_le_bytes());
+                header[14..16].copy_from_slice(&(body.len() as u16).to_le_bytes());
+                data.extend(header);
+                data.extend(body);
+            }
+        }
+        let size = data.len() as u32;
+        data[0x1c..0x20].copy_from_slice(&size.to_le_bytes());
+        data
+    }
+
+    fn error(data: &[u8]) -> String {
+        format_error(parse(data, &LoadOptions::default()))
+    }
+
+    #[test]
+    fn basic_disk_file() {
+        let image = parse(&disk(0xc1), &LoadOptions::default()).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x9000, data: vec![0x3e, 0x01, 0xc9] }]);
+        let names: Vec<(&str, BlockKind, Option<u16>)> = image.blocks.iter().map(|b| (b.name.as_str(), b.kind, b.address)).collect();
+        assert_eq!(names, [("IPL", BlockKind::Boot, None), ("PROG.BIN", BlockKind::Machine, Some(0x9000))]);
+    }
+
+    // セクタを指定して読む
+    #[test]
+    fn sectors() {
+        let options = LoadOptions{ org: 0x8000, select: Some(Select::Sectors{ track: 0, first: 1, last: 1 }), ..Default::default() };
+        let image = parse(&disk(0xc1), &options).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x8000, data: vec![0xc9; SECTOR_SIZE] }]);
+        let options = LoadOptions{ select: Some(Select::Sectors{ track: 0, first: 1, last: 2 }), ..Default::default() };
+        assert_eq!(format_error(parse(&disk(0xc1), &options)), "no sector 2 in track 0");
+    }
+
+    // IPL のないディスクや、FAT の壊れたディスクでも、セクタは読める
+    #[test]
+    fn sectors_without_ipl() {
+        let mut data = disk(0xc1);
+        data[TRACK_TABLE..TRACK_TABLE + 4].fill(0);         // トラック 0 がない
+        let image = parse(&data, &LoadOptions::default()).unwrap();
+        let names: Vec<&str> = image.blocks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["PROG.BIN"]);
+
+        let options = LoadOptions{ org: 0x8000, select: Some(Select::Sectors{ track: 1, first: 1, last: 1 }), ..Default::default() };
+        let image = parse(&data, &options).unwrap();
+        assert_eq!((image.segments[0].address, &image.segments[0].data[..4]), (0x8000, &[0x00, 0x90, 0x03, 0x90][..]));
+        assert!(parse(&disk(0x02), &options).is_ok());
+    }
+
+    #[test]
+    fn bad_fat() {
+        assert_eq!(error(&disk(0x02)), "PROG.BIN: FAT chain loops");
+        assert_eq!(error(&disk(0x03)), "PROG.BIN: no sector 9 in track 1");
+    }
+
+    #[test]
+    fn truncated() {
+        let data = disk(0xc1);
+        assert_eq!(error(&data[..HEADER_SIZE - 1]), "D88: header is truncated");
+        let cut = HEADER_SIZE + 16 + 100;                   // IPL のセクタの途中
+        assert_eq!(error(&data[..cut]), format!("D88: sector at {:X}H is truncated", HEADER_SIZE));
+    }
+}
@@ -0,0 +1,73 @@
+/*   Z80 Disassembler
+     name is loader/disk.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use super::{Block, BlockKind, Image, LoadError, LoadOptions, Select};
+
+// **********************************************
+//      ディスクイメージ (D88 / CPC の DSK) の共通部分
+//      トラック番号はイメージの中の順番 (両面なら シリンダ x 2 + 面)
+// **********************************************
+
+pub(super) struct Sector {
+    pub r: u8,              // セクタ番号
+    pub data: Vec<u8>,
+}
+
+pub(super) struct Disk {
+    pub tracks: Vec<Vec<Sector>>,
+}
+
+impl Disk {
+    pub fn sector(&self, track: usize, r: u8) -> Option<&[u8]> {
+        self.tracks.get(track)?.iter().find(|s| s.r == r).map(|s| s.data.as_slice())
+    }
+
+    // トラックの中のセクタ first..=last をつなげたもの
+    pub fn sectors(&self, track: usize, first: u8, last: u8) -> Result<Vec<u8>, LoadError> {
+        let mut data = Vec::new();
+        for r in first..=last {
+            let sector = self.sector(track, r)
+                .ok_or_else(|| LoadError::Format(format!("no sector {} in track {}", r, track)))?;
+            data.extend(sector);
+        }
+        Ok(data)
+    }
+
+    // **********************************************
+    //      ファイルの一覧から選んだものを置く
+    //      セクタを指定した時は、それを org に置く
+    // **********************************************
+    pub fn image(&self, blocks: Vec<Block>, options: &LoadOptions) -> Result<Image, LoadError> {
+        let Some(Select::Sectors { track, first, last }) = options.select else {
+            return Image::from_blocks(blocks, options);
+        };
+        let block = Block{ name: format!("T{}:{}-{}", track, first, last), kind: BlockKind::Data,
+                           address: None, exec: None, data: self.sectors(track, first, last)?, note: String::new() };
+        let mut image = Image::from_blocks(vec![block], &LoadOptions{ select: Some(Select::Number(1)), ..options.clone() })?;
+        image.blocks = blocks;
+        Ok(image)
+    }
+}
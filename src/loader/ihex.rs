@@ -0,0 +1,129 @@
+/*   Z80 Disassembler
+     name is loader/ihex.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+
+use super::{Image, LoadError};
+
+// **********************************************
+//      Intel HEX
+//
+//      :LLAAAATT(データ)CC  の行が並ぶ
+//          LL  データのバイト数      AAAA  アドレス
+//          TT  00 データ  01 終わり  02 拡張セグメントアドレス  03 開始セグメントアドレス
+//              04 拡張リニアアドレス  05 開始リニアアドレス
+//          CC  チェックサム (LL から CC まで足すと 0 になる)
+// **********************************************
+pub fn parse(data: &[u8]) -> Result<Image, LoadError> {
+    let text = String::from_utf8_lossy(data);
+    let mut memory: BTreeMap<u16, u8> = BTreeMap::new();
+    let mut entry_points = Vec::new();
+    let mut base: u32 = 0;          // 拡張アドレス
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| LoadError::Format(format!("line {}: {}", i + 1, message));
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some(hex) = line.strip_prefix(':') else {
+            return Err(error("record does not start with ':'"));
+        };
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(error("bad hex digits"));
+        }
+        let record: Vec<u8> = (0..hex.len()).step_by(2)
+                                            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16).unwrap())
+                                            .collect();
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(error("bad record length"));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(LoadError::Checksum(format!("line {}", i + 1)));
+        }
+        let offset = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let body = &record[4..record.len() - 1];
+        let value = || body.iter().fold(0u32, |v, b| (v << 8) | *b as u32);
+        match record[3] {
+            0x00 => {
+                for (j, byte) in body.iter().enumerate() {
+                    let address = base + ((offset + j as u32) & 0xffff);
+                    let Ok(address) = u16::try_from(address) else {
+                        return Err(error(&format!("address {:X}H is above FFFFH", address)));
+                    };
+                    memory.insert(address, *byte);
+                }
+            },
+            0x01 => break,
+            0x02 if body.len() == 2 => base = value() << 4,
+            0x04 if body.len() == 2 => base = value() << 16,
+            0x03 if body.len() == 4 => {        // CS:IP
+                let start = ((value() >> 16) << 4) + (value() & 0xffff);
+                entry_points.extend(u16::try_from(start));
+            },
+            0x05 if body.len() == 4 => entry_points.extend(u16::try_from(value())),
+            0x02..=0x05 => return Err(error("bad record length")),
+            t => return Err(error(&format!("unknown record type {:02X}", t))),
+        }
+    }
+    let mut image = Image::from_memory(&memory);
+    image.entry_points = entry_points;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+    use crate::loader::tests::format_error;
+
+    #[test]
+    fn records() {
+        let image = parse(b":038000003E01C975\r\n:02801000AABB09\r\n:040000050000800077\r\n:00000001FF\r\n:0100000011EE\r\n").unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x8000, data: vec![0x3e, 0x01, 0xc9] },
+                                    Segment{ address: 0x8010, data: vec![0xaa, 0xbb] }]);
+        assert_eq!(image.entry_points, [0x8000]);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(matches!(parse(b":038000003E01C975\n:02801000AABB0A\n"), Err(LoadError::Checksum(m)) if m == "line 2"));
+    }
+
+    // 途中で切れた行と、拡張アドレスで 64K を越えたところ
+    #[test]
+    fn errors() {
+        let cases = [
+            (":038000003E01C9",                     "line 1: bad record length"),
+            (":038000003E01C97",                    "line 1: bad hex digits"),
+            ("038000003E01C975",                    "line 1: record does not start with ':'"),
+            (":020000040001F9\n:0100000011EE\n",    "line 2: address 10000H is above FFFFH"),
+            (":020000021000EC\n:0100000011EE\n",    "line 2: address 10000H is above FFFFH"),
+        ];
+        for (text, message) in cases {
+            assert_eq!(format_error(parse(text.as_bytes())), message, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,383 @@
+/*   Z80 Disassembler
+     name is loader/mod.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::charcode::Charset;
+
+pub mod cmd;
+pub mod cmt;
+pub mod cpc;
+pub mod d88;
+mod disk;
+pub mod ihex;
+pub mod msx;
+pub mod mzt;
+pub mod p6;
+pub mod p6t;
+pub mod rom;
+pub mod srec;
+pub mod tap;
+pub mod zx;
+
+// **********************************************
+//      ファイルの読み込み
+//
+//      生のバイナリのほかに、アドレスを持ったファイル (Intel HEX / S レコード など) や、
+//      テープやディスクのイメージ (PC-6001 / D88 など) を読んで、メモリに置かれる形 (Image) にする
+// **********************************************
+
+// メモリに置かれる、ひとつながりのデータ
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u16,       // 先頭のアドレス
+    pub data: Vec<u8>,
+}
+
+// テープやディスクの中の、ひとつのファイル
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub name: String,               // ファイル名 (ないものは空)
+    pub kind: BlockKind,
+    pub address: Option<u16>,       // 読み込むアドレス (わからないものは None)
+    pub exec: Option<u16>,          // 実行開始アドレス
+    pub data: Vec<u8>,
+    pub note: String,               // 一覧に出す説明 (ボーレートなど)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+    Basic,          // BASIC のプログラム
+    Machine,        // 機械語
+    Data,           // ヘッダのないデータ
+    Boot,           // ディスクの IPL などの起動セクタ
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            BlockKind::Basic   => "basic",
+            BlockKind::Machine => "machine",
+            BlockKind::Data    => "data",
+            BlockKind::Boot    => "boot",
+        };
+        write!(f, "{:<8} {:<7}", self.name, kind)?;
+        match self.address {
+            Some(a) => write!(f, " {:04X}H-{:04X}H", a, a.wrapping_add(self.data.len().max(1) as u16 - 1))?,
+            None    => write!(f, " {} bytes", self.data.len())?,
+        }
+        if let Some(exec) = self.exec {
+            write!(f, "  exec {:04X}H", exec)?;
+        }
+        if !self.note.is_empty() {
+            write!(f, "  ({})", self.note)?;
+        }
+        Ok(())
+    }
+}
+
+// 読み込んだ結果
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,     // アドレス順  重ならない
+    pub entry_points: Vec<u16>,     // ファイルに書いてあった実行開始アドレス
+    pub blocks: Vec<Block>,         // テープなどの中にあったファイルの一覧
+}
+
+impl Image {
+    // **********************************************
+    //      アドレス -> バイト の表から、ひとつながりごとのセグメントにする
+    // **********************************************
+    pub fn from_memory(memory: &BTreeMap<u16, u8>) -> Image {
+        let mut segments: Vec<Segment> = Vec::new();
+        for (address, byte) in memory {
+            match segments.last_mut() {
+                Some(s) if s.address as usize + s.data.len() == *address as usize => s.data.push(*byte),
+                _ => segments.push(Segment{ address: *address, data: vec![*byte] }),
+            }
+        }
+        Image{ segments, ..Default::default() }
+    }
+
+    // アドレスのバイト (データのないところは None)
+    pub fn byte(&self, address: u16) -> Option<u8> {
+        self.segments.iter()
+            .find(|s| (s.address as usize..s.address as usize + s.data.len()).contains(&(address as usize)))
+            .map(|s| s.data[(address - s.address) as usize])
+    }
+
+    // アドレスのワード (リトルエンディアン)
+    pub fn word(&self, address: u16) -> Option<u16> {
+        Some(u16::from_le_bytes([self.byte(address)?, self.byte(address.wrapping_add(1))?]))
+    }
+
+    // **********************************************
+    //      テープなどのファイルの一覧から、選んだものをメモリに置く
+    //      選ばなかった時は、機械語のものをすべて置く
+    //      番号で選んだものにアドレスがなければ、org に置く
+    // **********************************************
+    pub fn from_blocks(blocks: Vec<Block>, options: &LoadOptions) -> Result<Image, LoadError> {
+        let chosen: Vec<(u16, &Block)> = match options.select {
+            Some(Select::Number(n)) => {
+                let Some(block) = n.checked_sub(1).and_then(|i| blocks.get(i)) else {
+                    return Err(LoadError::Format(format!("no block {} (there are {})", n, blocks.len())));
+                };
+                vec![(block.address.unwrap_or(options.org), block)]
+            },
+            Some(Select::All) => blocks.iter().filter_map(|b| Some((b.address?, b))).collect(),
+            Some(Select::Sectors { .. }) => {
+                return Err(LoadError::Format(String::from("sectors can be selected only in disk images")));
+            },
+            None => blocks.iter().filter(|b| b.kind == BlockKind::Machine)
+                                 .filter_map(|b| Some((b.address?, b)))
+                                 .collect(),
+        };
+        if chosen.is_empty() {
+            let list: Vec<String> = blocks.iter().enumerate().map(|(i, b)| format!("block {}: {}", i + 1, b)).collect();
+            return Err(LoadError::Format(format!("no machine language program (select a block with -n)\n{}", list.join("\n"))));
+        }
+        let mut memory: BTreeMap<u16, u8> = BTreeMap::new();
+        let mut entry_points = Vec::new();
+        for (address, block) in chosen {
+            for (i, byte) in block.data.iter().enumerate() {
+                memory.insert(address.wrapping_add(i as u16), *byte);
+            }
+            entry_points.extend(block.exec);
+        }
+        let mut image = Image::from_memory(&memory);
+        image.entry_points = entry_points;
+        image.blocks = blocks;
+        Ok(image)
+    }
+}
+
+// **********************************************
+//      ヘッダに書いてあるファイル名を文字列にする
+//      うしろの空白と 00H は取る  書けない文字は ?
+// **********************************************
+pub(crate) fn file_name(bytes: &[u8], charset: Charset) -> String {
+    let end = bytes.iter().rposition(|b| *b != b' ' && *b != 0).map_or(0, |i| i + 1);
+    bytes[..end].iter().map(|b| charset.to_text(*b).unwrap_or("?")).collect()
+}
+
+// **********************************************
+//      読み込み方の指定
+// **********************************************
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    pub format: Option<Format>,     // None の時は、拡張子と中身で決める
+    pub org: u16,                   // 生のバイナリと、アドレスのわからないブロックを置くアドレス
+    pub select: Option<Select>,     // テープなどの中のどのファイルを使うか (None なら機械語のものすべて)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Select {
+    All,                // アドレスのわかるものすべて
+    Number(usize),      // 一覧の番号 (1 から)
+    Sectors { track: usize, first: u8, last: u8 },     // ディスクのトラックの中のセクタ first..=last
+}
+
+// **********************************************
+//      ファイルの形式
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Binary,         // 生のバイナリ (アドレスは -o で指定する)
+    IntelHex,       // .hex / .ihx
+    SRecord,        // Motorola S レコード  .s19 / .s28 / .s37 / .srec / .mot
+    P6,             // PC-6001 のテープ  .cas / .p6
+    P6t,            // PC-6001 のテープ (フッタつき)  .p6t
+    D88,            // フロッピーディスクのイメージ  .d88
+    MsxRom,         // MSX の ROM カートリッジ  .rom
+    Bload,          // MSX の BLOAD ファイル  (FEH で始まる)
+    MsxCas,         // MSX のテープ  .cas (1FH A6H DEH BAH ... で始まる)
+    Sna,            // ZX Spectrum のスナップショット  .sna
+    Z80,            // ZX Spectrum のスナップショット  .z80
+    Tap,            // ZX Spectrum のテープ  .tap
+    Tzx,            // ZX Spectrum のテープ  .tzx
+    Amsdos,         // Amstrad CPC の AMSDOS ヘッダつきのファイル
+    CpcDsk,         // Amstrad CPC のディスクイメージ (標準 / 拡張)  .dsk
+    TrsCmd,         // TRS-80 のロードモジュール  /CMD
+    Cmt,            // PC-8001 / PC-8801 のテープ  .cmt
+    T88,            // PC-8801 のテープ (タグつき)  .t88
+    Mzt,            // Sharp MZ のテープ  .mzt / .mzf
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "bin" | "binary"       => Some(Format::Binary),
+            "hex" | "ihex" | "ihx" => Some(Format::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(Format::SRecord),
+            "p6" | "cas"           => Some(Format::P6),
+            "p6t"                  => Some(Format::P6t),
+            "d88" | "d77"          => Some(Format::D88),
+            "rom" | "mx1" | "mx2"  => Some(Format::MsxRom),
+            "bload"                => Some(Format::Bload),
+            "msxcas"               => Some(Format::MsxCas),
+            "sna"                  => Some(Format::Sna),
+            "z80"                  => Some(Format::Z80),
+            "tap"                  => Some(Format::Tap),
+            "tzx"                  => Some(Format::Tzx),
+            "amsdos"               => Some(Format::Amsdos),
+            "dsk"                  => Some(Format::CpcDsk),
+            "cmd"                  => Some(Format::TrsCmd),
+            "cmt"                  => Some(Format::Cmt),
+            "t88"                  => Some(Format::T88),
+            "mzt" | "mzf" | "m12"  => Some(Format::Mzt),
+            _                      => None,
+        }
+    }
+
+    // **********************************************
+    //      拡張子と中身から形式を決める
+    // **********************************************
+    pub fn detect(filename: &str, data: &[u8]) -> Format {
+        let extension = filename.rsplit_once('.').map(|(_, e)| e).unwrap_or_default();
+        let by_extension = Format::from_name(extension);
+        if by_extension == Some(Format::Binary) {   // .bin は中身によらず生のバイナリ
+            return Format::Binary;
+        }
+        if data.starts_with(&msx::CAS_SYNC) {       // .cas は PC-6001 と MSX の両方で使う
+            return Format::MsxCas;
+        }
+        if let Some(format) = by_extension.filter(|f| *f != Format::MsxRom || rom::has_header(data)) {
+            return format;
+        }
+        let text = data.iter().all(|b| b.is_ascii_hexdigit() || b":Ss\r\n".contains(b));
+        match data.first() {
+            Some(b':') if text             => return Format::IntelHex,
+            Some(b'S') if text
+                && data.get(1).is_some_and(|b| b.is_ascii_digit()) => return Format::SRecord,
+            _ => {},
+        }
+        if p6t::footer_position(data).is_some() {
+            return Format::P6t;
+        }
+        if data.starts_with(cmt::T88_ID) {
+            return Format::T88;
+        }
+        if cpc::has_header(data) {
+            return Format::Amsdos;
+        }
+        if msx::is_bload(data) {
+            return Format::Bload;
+        }
+        Format::Binary
+    }
+}
+
+// **********************************************
+//      読み込みのエラー
+// **********************************************
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Format(String),         // 形式が正しくない
+    Checksum(String),       // チェックサムが合わない
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e)       => write!(f, "{}", e),
+            LoadError::Format(m)   => write!(f, "{}", m),
+            LoadError::Checksum(m) => write!(f, "checksum error: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+// **********************************************
+//      ファイルを読み込む
+// **********************************************
+pub fn load(filename: &str, options: &LoadOptions) -> Result<Image, LoadError> {
+    let data = fs::read(filename)?;
+    match options.format.unwrap_or_else(|| Format::detect(filename, &data)) {
+        Format::Binary   => Ok(Image{ segments: vec![Segment{ address: options.org, data }], ..Default::default() }),
+        Format::IntelHex => ihex::parse(&data),
+        Format::SRecord  => srec::parse(&data),
+        Format::P6       => Image::from_blocks(p6::blocks(&data)?, options),
+        Format::P6t      => p6t::parse(&data, options),
+        Format::D88      => d88::parse(&data, options),
+        Format::MsxRom   => rom::parse(data),
+        Format::Bload    => Image::from_blocks(msx::bload(&data)?, options),
+        Format::MsxCas   => Image::from_blocks(msx::cas_blocks(&data)?, options),
+        Format::Sna      => zx::sna(&data, options),
+        Format::Z80      => zx::z80(&data, options),
+        Format::Tap      => Image::from_blocks(tap::tap_blocks(&data)?, options),
+        Format::Tzx      => Image::from_blocks(tap::tzx_blocks(&data)?, options),
+        Format::Amsdos   => Image::from_blocks(vec![cpc::amsdos(String::new(), &data)], options),
+        Format::CpcDsk   => cpc::dsk(&data, options),
+        Format::TrsCmd   => cmd::parse(&data),
+        Format::Cmt      => Image::from_blocks(cmt::blocks(&data)?, options),
+        Format::T88      => Image::from_blocks(cmt::t88_blocks(&data)?, options),
+        Format::Mzt      => Image::from_blocks(mzt::blocks(&data)?, options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 形式が正しくない時のメッセージ  ほかの結果は panic
+    pub fn format_error<T: fmt::Debug>(result: Result<T, LoadError>) -> String {
+        match result {
+            Err(LoadError::Format(m)) => m,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn detect() {
+        let bload = [0xfe, 0x00, 0x90, 0x02, 0x90, 0x00, 0x90, 0x3e, 0x01, 0xc9];
+        assert_eq!(Format::detect("GAME", &bload), Format::Bload);
+        assert_eq!(Format::detect("GAME.BIN", &bload), Format::Binary);
+        assert_eq!(Format::detect("game.binary", b":00000001FF"), Format::Binary);
+        assert_eq!(Format::detect("game.dat", b":00000001FF"), Format::IntelHex);
+        assert_eq!(Format::detect("game.rom", &[0xc9; 16]), Format::Binary);   // ヘッダのない .rom
+    }
+
+    // 隣り合ったアドレスは、ひとつのセグメントにまとめる
+    #[test]
+    fn from_memory() {
+        let memory = BTreeMap::from([(0x8000, 1), (0x8001, 2), (0x8003, 3), (0xffff, 4)]);
+        assert_eq!(Image::from_memory(&memory).segments,
+                   [Segment{ address: 0x8000, data: vec![1, 2] },
+                    Segment{ address: 0x8003, data: vec![3] },
+                    Segment{ address: 0xffff, data: vec![4] }]);
+    }
+}
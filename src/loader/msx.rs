@@ -0,0 +1,225 @@
+/*   Z80 Disassembler
+     name is loader/msx.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::p6::basic_program;
+use super::{file_name, Block, BlockKind, LoadError};
+
+// **********************************************
+//      MSX の BLOAD ファイル
+//          FEH + 開始アドレス(2) + 終了アドレス(2) + 実行アドレス(2) + データ
+//      終了アドレスも含む  ディスクに置くと 128 バイト単位でうしろに余りがつくことがある
+// **********************************************
+
+const BLOAD_ID: u8 = 0xfe;
+const BLOAD_HEADER: usize = 7;
+
+// BLOAD のヘッダか？ (長さがヘッダと合っているか、余りが 256 バイト未満)
+pub fn is_bload(data: &[u8]) -> bool {
+    let Some((start, end, _)) = bload_header(data) else {
+        return false;
+    };
+    if end < start {
+        return false;
+    }
+    let size = (end - start) as usize + 1;
+    (size..size + 256).contains(&(data.len() - BLOAD_HEADER))
+}
+
+fn bload_header(data: &[u8]) -> Option<(u16, u16, u16)> {
+    let header = data.get(..BLOAD_HEADER).filter(|h| h[0] == BLOAD_ID)?;
+    let word = |p: usize| u16::from_le_bytes([header[p], header[p + 1]]);
+    Some((word(1), word(3), word(5)))
+}
+
+pub fn bload(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let Some((start, end, exec)) = bload_header(data) else {
+        return Err(LoadError::Format(String::from("no BLOAD header (FEH)")));
+    };
+    let size = end.wrapping_sub(start) as usize + 1;
+    let Some(body) = data.get(BLOAD_HEADER..BLOAD_HEADER + size) else {
+        return Err(LoadError::Format(format!("file is truncated ({:04X}H-{:04X}H)", start, end)));
+    };
+    Ok(vec![Block{ name: String::new(), kind: BlockKind::Machine, address: Some(start),
+                   exec: (exec != 0).then_some(exec), data: body.to_vec(), note: String::new() }])
+}
+
+// **********************************************
+//      MSX のテープイメージ (.cas)
+//
+//      ブロックの前に 1FH A6H DEH BAH CCH 13H 7DH 74H (ファイルの中で 8 バイト単位の位置)
+//      ファイルは ヘッダのブロック + データのブロック
+//          ヘッダ  種類 x10 + ファイル名 6 バイト
+//              D0H  機械語 (BSAVE)  データは 開始・終了・実行アドレス + データ
+//              D3H  BASIC (中間語)  データはプログラム
+//              EAH  アスキー (SAVE)  データは 256 バイトずつのブロックで、1AH まで
+//      ヘッダのないブロックは、アドレスのわからないデータにする
+// **********************************************
+
+pub const CAS_SYNC: [u8; 8] = [0x1f, 0xa6, 0xde, 0xba, 0xcc, 0x13, 0x7d, 0x74];
+const MACHINE: u8 = 0xd0;
+const BASIC: u8 = 0xd3;
+const ASCII: u8 = 0xea;
+const TYPE_SIZE: usize = 10;
+const NAME_SIZE: usize = 6;
+const EOF: u8 = 0x1a;
+
+// 同期のマークで区切ったブロック
+fn cas_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let marks: Vec<usize> = (0..data.len()).step_by(8)
+                                           .filter(|p| data[*p..].starts_with(&CAS_SYNC))
+                                           .collect();
+    marks.iter().enumerate()
+         .map(|(i, p)| &data[p + CAS_SYNC.len()..marks.get(i + 1).copied().unwrap_or(data.len())])
+         .collect()
+}
+
+// ヘッダのブロックなら、種類とファイル名
+fn cas_header(chunk: &[u8]) -> Option<(u8, String)> {
+    let kind = *chunk.first()?;
+    let header = chunk.get(..TYPE_SIZE + NAME_SIZE)?;
+    (matches!(kind, MACHINE | BASIC | ASCII) && header[..TYPE_SIZE].iter().all(|b| *b == kind))
+        .then(|| (kind, file_name(&header[TYPE_SIZE..], Charset::Ascii)))
+}
+
+pub fn cas_blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let chunks = cas_chunks(data);
+    if chunks.is_empty() {
+        return Err(LoadError::Format(String::from("no MSX tape block (1FH A6H DEH BAH ...)")));
+    }
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < chunks.len() {
+        let Some((kind, name)) = cas_header(chunks[i]) else {
+            blocks.push(Block{ name: String::new(), kind: BlockKind::Data, address: None, exec: None,
+                               data: chunks[i].to_vec(), note: String::new() });
+            i += 1;
+            continue;
+        };
+        let Some(body) = chunks.get(i + 1).filter(|c| cas_header(c).is_none()) else {
+            return Err(LoadError::Format(format!("{}: data block is missing", name)));
+        };
+        i += 2;
+        let block = match kind {
+            MACHINE => {
+                let word = |p: usize| body.get(p..p + 2).map(|w| u16::from_le_bytes([w[0], w[1]]));
+                let (Some(start), Some(end), Some(exec)) = (word(0), word(2), word(4)) else {
+                    return Err(LoadError::Format(format!("header of {} is truncated", name)));
+                };
+                let size = end.wrapping_sub(start) as usize + 1;
+                let Some(data) = body.get(6..6 + size) else {
+                    return Err(LoadError::Format(format!("{} is truncated ({:04X}H-{:04X}H)", name, start, end)));
+                };
+                Block{ name, kind: BlockKind::Machine, address: Some(start), exec: (exec != 0).then_some(exec),
+                       data: data.to_vec(), note: String::new() }
+            },
+            BASIC => {
+                let (size, address) = basic_program(body).unwrap_or((body.len(), None));
+                Block{ name, kind: BlockKind::Basic, address, exec: None, data: body[..size].to_vec(), note: String::new() }
+            },
+            _ => {
+                // ----- アスキーは 1AH のあるブロックまで続く -----
+                let mut text = body.to_vec();
+                while !text.contains(&EOF) {
+                    match chunks.get(i).filter(|c| cas_header(c).is_none()) {
+                        Some(next) => text.extend(*next),
+                        None       => break,
+                    }
+                    i += 1;
+                }
+                if let Some(end) = text.iter().position(|b| *b == EOF) {
+                    text.truncate(end);
+                }
+                Block{ name, kind: BlockKind::Data, address: None, exec: None, data: text, note: String::from("ascii") }
+            },
+        };
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    const BLOAD: [u8; 10] = [BLOAD_ID, 0x00, 0x90, 0x02, 0x90, 0x00, 0x90, 0x3e, 0x01, 0xc9];
+
+    // 8 バイト単位の位置に同期のマークを置いて、ブロックを並べる
+    fn cas(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for chunk in chunks {
+            data.resize(data.len().next_multiple_of(8), 0x00);
+            data.extend(CAS_SYNC);
+            data.extend(*chunk);
+        }
+        data
+    }
+
+    fn header(kind: u8, name: &[u8; NAME_SIZE]) -> Vec<u8> {
+        let mut header = vec![kind; TYPE_SIZE];
+        header.extend(name);
+        header
+    }
+
+    #[test]
+    fn bload_file() {
+        let blocks = bload(&BLOAD).unwrap();
+        assert_eq!((blocks[0].address, blocks[0].exec), (Some(0x9000), Some(0x9000)));
+        assert_eq!(blocks[0].data, [0x3e, 0x01, 0xc9]);
+        // ディスクに置いた時の余り
+        let mut padded = BLOAD.to_vec();
+        padded.resize(128, 0x1a);
+        assert!(is_bload(&padded));
+        assert!(!is_bload(&BLOAD[..9]));
+        assert_eq!(format_error(bload(&BLOAD[..9])), "file is truncated (9000H-9002H)");
+        assert_eq!(format_error(bload(&BLOAD[1..])), "no BLOAD header (FEH)");
+    }
+
+    #[test]
+    fn cas_files() {
+        let data = cas(&[&header(MACHINE, b"GAME  "), &BLOAD[1..],
+                         &header(ASCII, b"TEXT  "), &[b'A'; 12], b"BC\x1a\x1a",
+                         &[0x12, 0x34]]);
+        let blocks = cas_blocks(&data).unwrap();
+        let summary: Vec<(&str, BlockKind, Option<u16>, &[u8])> =
+            blocks.iter().map(|b| (b.name.as_str(), b.kind, b.address, b.data.as_slice())).collect();
+        assert_eq!(summary[0], ("GAME", BlockKind::Machine, Some(0x9000), &[0x3e, 0x01, 0xc9][..]));
+        // アスキーは 8 バイト単位の余りの 00H もふくめて、1AH までつなげる
+        assert_eq!(summary[1].0, "TEXT");
+        assert!(summary[1].3.starts_with(&[b'A'; 12]) && summary[1].3.ends_with(b"BC"));
+        assert_eq!(summary[2], ("", BlockKind::Data, None, &[0x12, 0x34][..]));
+    }
+
+    #[test]
+    fn cas_truncated() {
+        assert_eq!(format_error(cas_blocks(&[0x00; 16])), "no MSX tape block (1FH A6H DEH BAH ...)");
+        assert_eq!(format_error(cas_blocks(&cas(&[&header(MACHINE, b"GAME  ")]))), "GAME: data block is missing");
+        assert_eq!(format_error(cas_blocks(&cas(&[&header(MACHINE, b"GAME  "), &BLOAD[1..4]]))), "header of GAME is truncated");
+        assert_eq!(format_error(cas_blocks(&cas(&[&header(MACHINE, b"GAME  "), &BLOAD[1..8]]))), "GAME is truncated (9000H-9002H)");
+    }
+}
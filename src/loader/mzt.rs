@@ -0,0 +1,113 @@
+/*   Z80 Disassembler
+     name is loader/mzt.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::{file_name, Block, BlockKind, LoadError};
+
+// **********************************************
+//      Sharp MZ-700 / MZ-80 のテープイメージ (.mzt / .mzf)
+//
+//      ヘッダ 128 バイト + 本体  を繰り返す (.mzf は１つだけ)
+//          00H     属性  01H 機械語 (OBJ)  02H BASIC (BTX)  ほかはデータ
+//          01H-11H ファイル名  0DH で終わる
+//          12H     本体の大きさ
+//          14H     読み込むアドレス
+//          16H     実行アドレス
+//          18H-7FH コメント
+// **********************************************
+
+const HEADER_SIZE: usize = 128;
+const NAME_SIZE: usize = 17;
+const OBJ: u8 = 0x01;
+const BTX: u8 = 0x02;
+
+pub fn blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let Some(header) = data.get(pos..pos + HEADER_SIZE) else {
+            return Err(LoadError::Format(format!("MZT: header at {:X}H is truncated", pos)));
+        };
+        let word = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let name = &header[1..1 + NAME_SIZE];
+        let name = file_name(name.split(|b| *b == 0x0d).next().unwrap_or_default(), Charset::Sharp);
+        let size = word(0x12) as usize;
+        let address = word(0x14);
+        pos += HEADER_SIZE;
+        let Some(body) = data.get(pos..pos + size) else {
+            return Err(LoadError::Format(format!("MZT: {} is truncated", name)));
+        };
+        let (kind, exec, note) = match header[0] {
+            OBJ => (BlockKind::Machine, Some(word(0x16)), String::new()),
+            BTX => (BlockKind::Basic, None, String::new()),
+            n   => (BlockKind::Data, None, format!("attribute {:02X}H", n)),
+        };
+        blocks.push(Block{ name, kind, address: Some(address), exec, data: body.to_vec(), note });
+        pos += size;
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    fn file(attribute: u8, name: &[u8], address: u16, exec: u16, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x00; HEADER_SIZE];
+        data[0] = attribute;
+        data[1..1 + name.len()].copy_from_slice(name);
+        data[1 + name.len()] = 0x0d;
+        data[0x12..0x14].copy_from_slice(&(body.len() as u16).to_le_bytes());
+        data[0x14..0x16].copy_from_slice(&address.to_le_bytes());
+        data[0x16..0x18].copy_from_slice(&exec.to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn files() {
+        let mut data = file(OBJ, b"GAME", 0x1200, 0x1203, &[0x00, 0x00, 0x00, 0xc9]);
+        data.extend(file(BTX, b"LIST", 0x6bcf, 0x0000, &[0x00, 0x00]));
+        data.extend(file(0x03, b"DATA", 0x0000, 0x0000, &[0x41]));
+        let blocks = blocks(&data).unwrap();
+        let summary: Vec<_> = blocks.iter().map(|b| (b.name.as_str(), b.kind, b.address, b.exec, b.note.as_str())).collect();
+        assert_eq!(summary, [("GAME", BlockKind::Machine, Some(0x1200), Some(0x1203), ""),
+                             ("LIST", BlockKind::Basic, Some(0x6bcf), None, ""),
+                             ("DATA", BlockKind::Data, Some(0x0000), None, "attribute 03H")]);
+        assert_eq!(blocks[0].data, [0x00, 0x00, 0x00, 0xc9]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = file(OBJ, b"GAME", 0x1200, 0x1200, &[0xc9]);
+        assert_eq!(format_error(blocks(&data[..HEADER_SIZE - 1])), "MZT: header at 0H is truncated");
+        assert_eq!(format_error(blocks(&data[..HEADER_SIZE])), "MZT: GAME is truncated");
+        data.push(0x01);
+        assert_eq!(format_error(blocks(&data)), "MZT: header at 81H is truncated");
+    }
+}
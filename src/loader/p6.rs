@@ -0,0 +1,164 @@
+/*   Z80 Disassembler
+     name is loader/p6.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::{file_name, Block, BlockKind, LoadError};
+
+// **********************************************
+//      PC-6001 のテープイメージ (.cas / .p6)
+//
+//      テープから読んだバイト列がそのまま並んでいる
+//          BASIC   D3H x10 + ファイル名 6 バイト + プログラム (行のリンクが 0000H で終わり)
+//      ファイルの間の 00H は読み飛ばす
+//      機械語 (CSAVEM など) にはアドレスの入ったヘッダがないので、アドレスのわからないデータにする
+//      (-n で選んで -o のアドレスに置く  P6T ならオートスタートの EXEC &HXXXX から実行の流れをたどる)
+//      D0H x10 + 開始・終了・実行アドレスのヘッダは MSX のテープの形で、PC-6001 の資料では確かめられないので読まない
+// **********************************************
+
+const BASIC: u8 = 0xd3;
+const HEADER_SIZE: usize = 10;      // D3H が 10 個続くとヘッダ
+const NAME_SIZE: usize = 6;
+
+// **********************************************
+//      テープの中のファイルを、順番に取り出す
+// **********************************************
+pub fn blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        if !header_at(data, pos) {
+            // ----- ヘッダのないデータ (次のヘッダまで) -----
+            let end = (pos..data.len()).find(|p| header_at(data, *p)).unwrap_or(data.len());
+            let chunk = &data[pos..end];
+            if chunk.iter().any(|b| *b != 0) {
+                blocks.push(Block{ name: String::new(), kind: BlockKind::Data, address: None, exec: None, data: chunk.to_vec(), note: String::new() });
+            }
+            pos = end;
+            continue;
+        };
+        pos += HEADER_SIZE;
+        let Some(name) = data.get(pos..pos + NAME_SIZE) else {
+            return Err(LoadError::Format(String::from("file name is truncated")));
+        };
+        let name = file_name(name, Charset::Pc6001);
+        pos += NAME_SIZE;
+
+        let (size, address) = basic_program(&data[pos..])
+            .ok_or_else(|| LoadError::Format(format!("BASIC program {} is truncated", name)))?;
+        blocks.push(Block{ name, kind: BlockKind::Basic, address, exec: None, data: data[pos..pos + size].to_vec(), note: String::new() });
+        pos += size;
+        while data.get(pos) == Some(&0) {   // プログラムのうしろの 00H
+            pos += 1;
+        }
+    }
+    Ok(blocks)
+}
+
+// pos から BASIC のヘッダ (D3H が 10 個) か？
+fn header_at(data: &[u8], pos: usize) -> bool {
+    data.get(pos..pos + HEADER_SIZE).is_some_and(|h| h.iter().all(|b| *b == BASIC))
+}
+
+// **********************************************
+//      BASIC のプログラムの長さと、置かれていたアドレス
+//      各行は  次の行のアドレス(2) 行番号(2) 本文 00H  で、次の行のアドレスが 0000H なら終わり
+//      最初の行のリンクから、プログラムの先頭アドレスがわかる
+// **********************************************
+pub(super) fn basic_program(body: &[u8]) -> Option<(usize, Option<u16>)> {
+    let mut offset = 0;
+    let mut first_link = None;
+    let mut second_line = 0;
+    loop {
+        let link = u16::from_le_bytes([*body.get(offset)?, *body.get(offset + 1)?]);
+        if link == 0 {
+            offset += 2;
+            break;
+        }
+        let end = body.get(offset + 4..)?.iter().position(|b| *b == 0)?;
+        offset += 4 + end + 1;
+        if first_link.is_none() {
+            first_link = Some(link);
+            second_line = offset;
+        }
+    }
+    Some((offset, first_link.map(|link| link.wrapping_sub(second_line as u16))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{Image, LoadOptions, Segment, Select};
+    use crate::loader::tests::format_error;
+
+    const PROGRAM: [u8; 9] = [0x3e, 0xc4, 0x0a, 0x00, 0x91, b'1', 0x00,   // C437  10 PRINT1
+                              0x00, 0x00];                                // C43E  終わり
+
+    fn tape(name: &[u8], program: &[u8]) -> Vec<u8> {
+        let mut data = vec![BASIC; HEADER_SIZE];
+        data.extend(name);
+        data.extend(program);
+        data
+    }
+
+    #[test]
+    fn basic_and_data() {
+        let mut data = tape(b"HELLO ", &PROGRAM);
+        data.extend([0x00, 0x00, 0x00, 0x3e, 0x01, 0xc9]);
+        let blocks = blocks(&data).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].name.as_str(), blocks[0].kind, blocks[0].address), ("HELLO", BlockKind::Basic, Some(0xc437)));
+        assert_eq!(blocks[0].data, PROGRAM);
+        // 00H は読み飛ばすので、機械語はアドレスのわからないデータになる
+        assert_eq!((blocks[1].kind, blocks[1].address), (BlockKind::Data, None));
+        assert_eq!(blocks[1].data, [0x3e, 0x01, 0xc9]);
+    }
+
+    // 機械語は -n で選んで、-o のアドレスに置く
+    #[test]
+    fn machine_at_org() {
+        let mut data = tape(b"HELLO ", &PROGRAM);
+        data.extend([0x00, 0x00, 0x3e, 0x01, 0xc9]);
+        let options = LoadOptions{ org: 0xd000, select: Some(Select::Number(2)), ..Default::default() };
+        let image = Image::from_blocks(blocks(&data).unwrap(), &options).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0xd000, data: vec![0x3e, 0x01, 0xc9] }]);
+    }
+
+    // D3H が 10 個に足りなければヘッダではない
+    #[test]
+    fn short_header() {
+        let data = [BASIC; HEADER_SIZE - 1];
+        let blocks = blocks(&data).unwrap();
+        assert_eq!((blocks.len(), blocks[0].kind), (1, BlockKind::Data));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(format_error(blocks(&tape(b"HEL", &[]))), "file name is truncated");
+        assert_eq!(format_error(blocks(&tape(b"HELLO ", &PROGRAM[..7]))), "BASIC program HELLO is truncated");
+        assert_eq!(format_error(blocks(&tape(b"HELLO ", &PROGRAM[..5]))), "BASIC program HELLO is truncated");
+    }
+}
@@ -0,0 +1,173 @@
+/*   Z80 Disassembler
+     name is loader/p6t.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::{file_name, p6, BlockKind, Image, LoadError, LoadOptions};
+
+// **********************************************
+//      PC-6001 のテープイメージ (.p6t)
+//
+//      .p6 と同じテープのバイト列のうしろに、フッタがついている
+//      フッタ
+//          "P6" / バージョン(1) = 2 / DATA ブロックの数(1) / オートスタート(1) 0:なし 1:あり
+//          BASIC モード(1) / ページ数(1) / オートスタートのコマンドの長さ(2) / コマンド
+//          拡張情報の長さ(2) / 拡張情報
+//      DATA ブロック (数だけ続く)
+//          "TI" / ID(1) / 名前(16) / ボーレート(2) / 無音部の時間(2) / ぴー音の時間(2)
+//          テープのバイト列の中のオフセット(4) / サイズ(4)
+//      ファイルの最後の 4 バイトが、フッタの位置
+//      数値はすべてリトルエンディアン
+// **********************************************
+
+const NAME_SIZE: usize = 16;
+
+// フッタの位置  P6T でなければ None
+pub fn footer_position(data: &[u8]) -> Option<usize> {
+    let tail = data.len().checked_sub(4)?;
+    let position = u32::from_le_bytes(data[tail..].try_into().ok()?) as usize;
+    (position < tail && data.get(position..position + 3) == Some(b"P6\x02")).then_some(position)
+}
+
+pub fn parse(data: &[u8], options: &LoadOptions) -> Result<Image, LoadError> {
+    let Some(footer) = footer_position(data) else {
+        return Err(LoadError::Format(String::from("P6T footer not found")));
+    };
+    let mut pos = footer;
+    let truncated = || LoadError::Format(String::from("P6T footer is truncated"));
+    let byte = |p: usize| data.get(p).copied().ok_or_else(truncated);
+    let word = |p: usize| Ok::<usize, LoadError>(byte(p)? as usize | (byte(p + 1)? as usize) << 8);
+    let long = |p: usize| Ok::<usize, LoadError>(word(p)? | word(p + 2)? << 16);
+
+    // ----- フッタ -----
+    let count = byte(pos + 3)?;
+    let autostart = byte(pos + 4)? != 0;
+    let command_size = word(pos + 7)?;
+    let command = data.get(pos + 9..pos + 9 + command_size).ok_or_else(truncated)?;
+    pos += 9 + command_size;
+    pos += 2 + word(pos)?;              // 拡張情報は使わない
+
+    // ----- DATA ブロック -----
+    let mut blocks = Vec::new();
+    for _ in 0..count {
+        if data.get(pos..pos + 2) != Some(b"TI") {
+            return Err(LoadError::Format(format!("DATA block not found at {:X}H", pos)));
+        }
+        let name = file_name(data.get(pos + 3..pos + 3 + NAME_SIZE).ok_or_else(truncated)?, Charset::Pc6001);
+        let name = name.trim_end_matches('?').to_string();      // 00H で終わる
+        let baud = word(pos + 19)?;
+        let offset = long(pos + 25)?;
+        let size = long(pos + 29)?;
+        pos += 33;
+
+        let Some(tape) = data[..footer].get(offset..offset + size) else {
+            return Err(LoadError::Format(format!("DATA block {} is outside the tape", name)));
+        };
+        // ヘッダのあるものは .p6 と同じに読む  ヘッダのないものは DATA ブロックの名前をつける
+        let mut found = p6::blocks(tape)?;
+        for block in &mut found {
+            if block.kind == BlockKind::Data && block.name.is_empty() {
+                block.name.clone_from(&name);
+            }
+            block.note = format!("{} baud", baud);
+        }
+        blocks.extend(found);
+    }
+
+    let mut image = Image::from_blocks(blocks, options)?;
+    if autostart {
+        image.entry_points.extend(autostart_address(command));
+    }
+    Ok(image)
+}
+
+// **********************************************
+//      オートスタートのコマンドから、機械語の開始アドレスを探す
+//      EXEC &H9000 のように書いてあるもの
+// **********************************************
+fn autostart_address(command: &[u8]) -> Option<u16> {
+    let text = String::from_utf8_lossy(command).to_ascii_uppercase();
+    let rest = &text[text.find("EXEC")? + 4..];
+    let hex = rest.trim_start().strip_prefix("&H")?;
+    let end = hex.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(hex.len());
+    u16::from_str_radix(&hex[..end], 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{Segment, Select};
+    use crate::loader::tests::format_error;
+
+    const COMMAND: &[u8] = b"CLEAR,&H8FFF:EXEC &H9000\r";
+
+    // 機械語 3 バイトのテープに、DATA ブロック１つのフッタをつける
+    fn p6t() -> Vec<u8> {
+        let mut data = vec![0x3e, 0x01, 0xc9];
+        let position = data.len() as u32;
+        data.extend(b"P6\x02\x01\x01\x01\x04");
+        data.extend((COMMAND.len() as u16).to_le_bytes());
+        data.extend(COMMAND);
+        data.extend([0x00, 0x00]);
+        data.extend(b"TI\x00PROG\0\0\0\0\0\0\0\0\0\0\0\0");
+        data.extend([0xb0, 0x04, 0x00, 0x00, 0x00, 0x00]);
+        data.extend(0u32.to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend(position.to_le_bytes());
+        data
+    }
+
+    fn error(data: &[u8]) -> String {
+        format_error(parse(data, &LoadOptions{ select: Some(Select::Number(1)), ..Default::default() }))
+    }
+
+    #[test]
+    fn data_block_and_autostart() {
+        let options = LoadOptions{ org: 0x9000, select: Some(Select::Number(1)), ..Default::default() };
+        let image = parse(&p6t(), &options).unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x9000, data: vec![0x3e, 0x01, 0xc9] }]);
+        assert_eq!(image.entry_points, [0x9000]);
+        assert_eq!((image.blocks[0].name.as_str(), image.blocks[0].note.as_str()), ("PROG", "1200 baud"));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = p6t();
+        let position = 3 + 9 + COMMAND.len() + 2;
+        let mut cut = data[..position + 20].to_vec();       // DATA ブロックの途中まで
+        cut.extend(3u32.to_le_bytes());
+        assert_eq!(error(&cut), "P6T footer is truncated");
+        assert_eq!(error(&data[..data.len() - 1]), "P6T footer not found");
+
+        let mut outside = data.clone();                     // サイズがテープより大きい
+        outside[position + 29] = 4;
+        assert_eq!(error(&outside), "DATA block PROG is outside the tape");
+
+        let mut lost = data.clone();
+        lost[position] = b'X';
+        assert_eq!(error(&lost), format!("DATA block not found at {:X}H", position));
+    }
+}
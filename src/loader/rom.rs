@@ -0,0 +1,112 @@
+/*   Z80 Disassembler
+     name is loader/rom.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use super::{Image, LoadError, Segment};
+
+// **********************************************
+//      MSX の ROM カートリッジ
+//
+//      先頭の 16 バイトがヘッダ
+//          +00H "AB"  +02H INIT  +04H STATEMENT  +06H DEVICE  +08H TEXT  +0AH 予約(6)
+//      ふつうは 4000H から置かれる  ポインタが 8000H-BFFFH だけを指していれば 8000H から
+//      (BASIC のカートリッジなど)
+//      4000H にヘッダがある 0000H からのダンプ (48K / 64K) もそのまま置く
+// **********************************************
+
+pub const HEADER_SIZE: u16 = 16;
+
+// 先頭か 4000H に "AB" があるか？  (ない .rom はふつうのバイナリとして読む)
+pub fn has_header(data: &[u8]) -> bool {
+    data.starts_with(b"AB") || data.get(0x4000..0x4002) == Some(b"AB")
+}
+
+pub fn parse(data: Vec<u8>) -> Result<Image, LoadError> {
+    let address = if data.starts_with(b"AB") {
+        let pointers: Vec<u16> = (2..10).step_by(2)
+                                        .filter_map(|i| Some(u16::from_le_bytes([*data.get(i)?, *data.get(i + 1)?])))
+                                        .filter(|p| *p != 0)
+                                        .collect();
+        let page2 = |p: &u16| (0x8000..0xc000).contains(p);
+        if !pointers.is_empty() && pointers.iter().all(page2) { 0x8000 } else { 0x4000 }
+    } else if data.get(0x4000..0x4002) == Some(b"AB") {
+        0x0000
+    } else {
+        return Err(LoadError::Format(String::from("no MSX ROM header (AB) at the top or at 4000H")));
+    };
+    if address + data.len() > 0x10000 {
+        return Err(LoadError::Format(format!("ROM is too big to place at {:04X}H ({} bytes, MegaROM banks are not supported)",
+                                             address, data.len())));
+    }
+    Ok(Image{ segments: vec![Segment{ address: address as u16, data }], ..Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    // INIT だけのヘッダ
+    fn rom(init: u16, size: usize) -> Vec<u8> {
+        let mut data = vec![0x00; size];
+        data[..2].copy_from_slice(b"AB");
+        data[2..4].copy_from_slice(&init.to_le_bytes());
+        data
+    }
+
+    fn address(data: Vec<u8>) -> u16 {
+        parse(data).unwrap().segments[0].address
+    }
+
+    #[test]
+    fn placement() {
+        assert_eq!(address(rom(0x4010, 0x4000)), 0x4000);
+        assert_eq!(address(rom(0x8010, 0x4000)), 0x8000);       // BASIC のカートリッジ
+        let mut dump = vec![0x00; 0xc000];
+        dump[0x4000..0x4010].copy_from_slice(&rom(0x4010, 16));
+        assert_eq!(address(dump), 0x0000);
+    }
+
+    // ヘッダのないものは ROM として読まない (ふつうのバイナリにする)
+    #[test]
+    fn no_header() {
+        assert!(has_header(&rom(0x4010, 16)));
+        assert!(!has_header(b"A"));
+        assert!(!has_header(&[0x00; 0x4001]));
+        assert_eq!(format_error(parse(vec![0xc9; 16])), "no MSX ROM header (AB) at the top or at 4000H");
+    }
+
+    #[test]
+    fn too_big() {
+        assert_eq!(format_error(parse(rom(0x4010, 0xc001))), "ROM is too big to place at 4000H (49153 bytes, MegaROM banks are not supported)");
+    }
+
+    // ヘッダが途中で切れていても、読めるポインタだけで決める
+    #[test]
+    fn truncated_header() {
+        assert_eq!(address(rom(0x8010, 16)[..3].to_vec()), 0x4000);
+        assert_eq!(address(rom(0x8010, 16)[..4].to_vec()), 0x8000);
+    }
+}
@@ -0,0 +1,132 @@
+/*   Z80 Disassembler
+     name is loader/srec.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::BTreeMap;
+
+use super::{Image, LoadError};
+
+// **********************************************
+//      Motorola S レコード
+//
+//      S(種類)(バイト数)(アドレス)(データ)(チェックサム)  の行が並ぶ
+//          S0 ヘッダ  S1 / S2 / S3 データ (アドレスが 2 / 3 / 4 バイト)
+//          S5 / S6 レコード数  S9 / S8 / S7 開始アドレス (2 / 3 / 4 バイト)
+//          バイト数はアドレスからチェックサムまで
+//          チェックサムは、バイト数からデータまでの合計の下位バイトを反転したもの
+// **********************************************
+pub fn parse(data: &[u8]) -> Result<Image, LoadError> {
+    let text = String::from_utf8_lossy(data);
+    let mut memory: BTreeMap<u16, u8> = BTreeMap::new();
+    let mut entry_points = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| LoadError::Format(format!("line {}: {}", i + 1, message));
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        let (Some('S' | 's'), Some(kind)) = (chars.next(), chars.next().and_then(|c| c.to_digit(10))) else {
+            return Err(error("record does not start with S0-S9"));
+        };
+        let hex = chars.as_str();
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(error("bad hex digits"));
+        }
+        let record: Vec<u8> = (0..hex.len()).step_by(2)
+                                            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16).unwrap())
+                                            .collect();
+        let address_size = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8     => 3,
+            3 | 7         => 4,
+            _             => return Err(error(&format!("unknown record type S{}", kind))),
+        };
+        if record.len() < address_size + 2 || record.len() != record[0] as usize + 1 {
+            return Err(error("bad record length"));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+            return Err(LoadError::Checksum(format!("line {}", i + 1)));
+        }
+        let address = record[1..=address_size].iter().fold(0u32, |v, b| (v << 8) | *b as u32);
+        let body = &record[address_size + 1..record.len() - 1];
+        match kind {
+            1..=3 => {
+                for (j, byte) in body.iter().enumerate() {
+                    let Ok(address) = u16::try_from(address + j as u32) else {
+                        return Err(error(&format!("address {:X}H is above FFFFH", address + j as u32)));
+                    };
+                    memory.insert(address, *byte);
+                }
+            },
+            7..=9 => {
+                let Ok(start) = u16::try_from(address) else {
+                    return Err(error(&format!("start address {:X}H is above FFFFH", address)));
+                };
+                entry_points.push(start);
+            },
+            _ => {},                    // S0 ヘッダと S5 / S6 レコード数は使わない
+        }
+    }
+    let mut image = Image::from_memory(&memory);
+    image.entry_points = entry_points;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Segment;
+    use crate::loader::tests::format_error;
+
+    #[test]
+    fn records() {
+        let image = parse(b"S00600004844521B\r\nS10680003E01C971\r\nS206008010AABB04\r\nS5030002FA\r\nS90380007C\r\n").unwrap();
+        assert_eq!(image.segments, [Segment{ address: 0x8000, data: vec![0x3e, 0x01, 0xc9] },
+                                    Segment{ address: 0x8010, data: vec![0xaa, 0xbb] }]);
+        assert_eq!(image.entry_points, [0x8000]);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(matches!(parse(b"S10680003E01C971\nS206008010AABB05\n"), Err(LoadError::Checksum(m)) if m == "line 2"));
+    }
+
+    // S1-S3 / S7-S9 でアドレスの長さが変わるところ
+    #[test]
+    fn errors() {
+        let cases = [
+            ("S106",                "line 1: bad record length"),
+            ("S10680003E01C97",     "line 1: bad hex digits"),
+            ("S",                   "line 1: record does not start with S0-S9"),
+            ("S4030002FA",          "line 1: unknown record type S4"),
+            ("S3060001000011E7",    "line 1: address 10000H is above FFFFH"),
+            ("S70500010000F9",      "line 1: start address 10000H is above FFFFH"),
+        ];
+        for (text, message) in cases {
+            assert_eq!(format_error(parse(text.as_bytes())), message, "{}", text);
+        }
+    }
+}
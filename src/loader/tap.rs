@@ -0,0 +1,313 @@
+/*   Z80 Disassembler
+     name is loader/tap.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::charcode::Charset;
+
+use super::{file_name, Block, BlockKind, LoadError};
+
+// **********************************************
+//      ZX Spectrum のテープ (.tap / .tzx)
+//
+//      テープのブロックは  フラグ(1) + データ + チェックサム(1)  (全部の XOR が 0)
+//          フラグ 00H  ヘッダ  種類(1) 名前(10) 長さ(2) パラメータ1(2) パラメータ2(2)
+//              種類 0 BASIC (パラメータ1 は自動実行の行)  1 数値配列  2 文字配列
+//                   3 CODE (パラメータ1 は開始アドレス)
+//          フラグ FFH  データ  ヘッダの次にあれば、そのヘッダのファイル
+// **********************************************
+
+const HEADER_FLAG: u8 = 0x00;
+const HEADER_SIZE: usize = 19;      // フラグ + 17 バイト + チェックサム
+const TZX_ID: &[u8] = b"ZXTape!\x1a";
+
+// テープの上の１ブロック
+struct TapeBlock {
+    data: Vec<u8>,          // フラグとチェックサムも含む
+    note: &'static str,     // 速度の違うブロックなど
+    standard: bool,         // ROM のルーチンで読める形 (フラグとチェックサムがある)
+}
+
+impl TapeBlock {
+    fn is_header(&self) -> bool {
+        self.standard && self.data.len() == HEADER_SIZE && self.data[0] == HEADER_FLAG
+    }
+
+    // フラグとチェックサムを除いた中身
+    fn payload(&self) -> &[u8] {
+        if self.standard && self.data.len() >= 2 {
+            &self.data[1..self.data.len() - 1]
+        } else {
+            &self.data
+        }
+    }
+
+    fn checksum_ok(&self) -> bool {
+        !self.standard || self.data.iter().fold(0, |sum, b| sum ^ b) == 0
+    }
+}
+
+// **********************************************
+//      .tap  長さ(2) + ブロック の繰り返し
+// **********************************************
+pub fn tap_blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    let mut tape = Vec::new();
+    let mut pos = 0;
+    while pos + 2 <= data.len() {
+        let length = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        let Some(block) = data.get(pos + 2..pos + 2 + length) else {
+            return Err(LoadError::Format(format!("TAP: block at {:X}H is truncated", pos)));
+        };
+        tape.push(TapeBlock{ data: block.to_vec(), note: "", standard: true });
+        pos += 2 + length;
+    }
+    Ok(files(&tape))
+}
+
+// **********************************************
+//      .tzx  "ZXTape!" 1AH 版数(2) + ID(1) つきのブロックの繰り返し
+//      10H (標準) と 11H (ターボ) はふつうのブロックとして読む
+//      14H (データだけ) は中身をそのまま出す  ほかの ID は長さを見て飛ばす
+// **********************************************
+pub fn tzx_blocks(data: &[u8]) -> Result<Vec<Block>, LoadError> {
+    if !data.starts_with(TZX_ID) || data.len() < 10 {
+        return Err(LoadError::Format(String::from("TZX: no \"ZXTape!\" header")));
+    }
+    let mut tape = Vec::new();
+    let mut pos = 10;
+    while pos < data.len() {
+        let id = data[pos];
+        let at = |offset: usize, size: usize| -> Result<usize, LoadError> {
+            let bytes = data.get(pos + 1 + offset..pos + 1 + offset + size)
+                .ok_or_else(|| LoadError::Format(format!("TZX: block {:02X}H at {:X}H is truncated", id, pos)))?;
+            Ok(bytes.iter().rev().fold(0, |n, b| n << 8 | *b as usize))
+        };
+        // (ヘッダの長さ, データの長さ, データがあればそのブロック)
+        let (header, length, kind): (usize, usize, Option<(&'static str, bool)>) = match id {
+            0x10 => (4, at(2, 2)?, Some(("", true))),
+            0x11 => (18, at(15, 3)?, Some(("turbo", true))),
+            0x12 => (4, 0, None),
+            0x13 => (1, at(0, 1)? * 2, None),
+            0x14 => (10, at(7, 3)?, Some(("pure data", false))),
+            0x15 => (8, at(5, 3)?, None),
+            0x18 | 0x19 | 0x2b => (4, at(0, 4)?, None),
+            0x20 | 0x23 | 0x24 => (2, 0, None),
+            0x21 | 0x30 => (1, at(0, 1)?, None),
+            0x22 | 0x25 | 0x27 => (0, 0, None),
+            0x26 => (2, at(0, 2)? * 2, None),
+            0x28 | 0x32 => (2, at(0, 2)?, None),
+            0x2a => (4, 0, None),
+            0x31 => (2, at(1, 1)?, None),
+            0x33 => (1, at(0, 1)? * 3, None),
+            0x35 => (20, at(16, 4)?, None),
+            0x40 => (4, at(1, 3)?, None),
+            0x5a => (9, 0, None),
+            _ => return Err(LoadError::Format(format!("TZX: unknown block {:02X}H at {:X}H", id, pos))),
+        };
+        let start = pos + 1 + header;
+        let Some(body) = data.get(start..start + length) else {
+            return Err(LoadError::Format(format!("TZX: block {:02X}H at {:X}H is truncated", id, pos)));
+        };
+        if let Some((note, standard)) = kind {
+            tape.push(TapeBlock{ data: body.to_vec(), note, standard });
+        }
+        pos = start + length;
+    }
+    Ok(files(&tape))
+}
+
+// **********************************************
+//      ヘッダとデータを組にして、ファイルの一覧にする
+// **********************************************
+fn files(tape: &[TapeBlock]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < tape.len() {
+        let block = &tape[i];
+        let next = tape.get(i + 1).filter(|b| !b.is_header());
+        let (Some(data), true) = (next, block.is_header()) else {
+            // ----- ヘッダのないデータ -----
+            let mut note = block.note.to_string();
+            if !block.checksum_ok() {
+                note = join(&note, "checksum error");
+            }
+            if block.standard && !block.data.is_empty() {
+                note = join(&note, &format!("flag {:02X}H", block.data[0]));
+            }
+            blocks.push(Block{ name: String::new(), kind: BlockKind::Data, address: None, exec: None,
+                               data: block.payload().to_vec(), note });
+            i += 1;
+            continue;
+        };
+        i += 2;
+
+        let header = block.payload();
+        let word = |p: usize| u16::from_le_bytes([header[p], header[p + 1]]);
+        let name = file_name(&header[1..11], Charset::Ascii);
+        let length = word(11) as usize;
+        let param = word(13);
+        let mut body = data.payload().to_vec();
+        body.truncate(length);
+        let mut note = data.note.to_string();
+        if !block.checksum_ok() || !data.checksum_ok() {
+            note = join(&note, "checksum error");
+        }
+        if body.len() < length {
+            note = join(&note, &format!("{} bytes missing", length - body.len()));
+        }
+        let (kind, address) = match header[0] {
+            0 => {
+                if param < 0x8000 {
+                    note = join(&note, &format!("LINE {}", param));
+                }
+                (BlockKind::Basic, None)
+            },
+            3 => (BlockKind::Machine, Some(param)),
+            1 => { note = join(&note, "number array"); (BlockKind::Data, None) },
+            _ => { note = join(&note, "character array"); (BlockKind::Data, None) },
+        };
+        blocks.push(Block{ name, kind, address, exec: None, data: body, note });
+    }
+    blocks
+}
+
+// 説明をつなげる
+fn join(note: &str, more: &str) -> String {
+    if note.is_empty() { more.to_string() } else { format!("{}, {}", note, more) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    // フラグとチェックサムをつけたブロック
+    fn standard(flag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut block = vec![flag];
+        block.extend(payload);
+        block.push(block.iter().fold(0, |sum, b| sum ^ b));
+        block
+    }
+
+    fn header(kind: u8, name: &[u8; 10], length: u16, param: u16) -> Vec<u8> {
+        let mut payload = vec![kind];
+        payload.extend(name);
+        payload.extend(length.to_le_bytes());
+        payload.extend(param.to_le_bytes());
+        payload.extend(0x8000u16.to_le_bytes());
+        standard(HEADER_FLAG, &payload)
+    }
+
+    fn tap(blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for block in blocks {
+            data.extend((block.len() as u16).to_le_bytes());
+            data.extend(block);
+        }
+        data
+    }
+
+    fn summary(blocks: &[Block]) -> Vec<(&str, BlockKind, Option<u16>, &str)> {
+        blocks.iter().map(|b| (b.name.as_str(), b.kind, b.address, b.note.as_str())).collect()
+    }
+
+    #[test]
+    fn tap_files() {
+        let code = standard(0xff, &[0x3e, 0x01, 0xc9]);
+        let blocks = tap_blocks(&tap(&[header(3, b"game      ", 3, 0x8000), code.clone(),
+                                       header(0, b"loader    ", 2, 10), standard(0xff, &[0x00, 0x0a]),
+                                       code])).unwrap();
+        assert_eq!(summary(&blocks), [("game", BlockKind::Machine, Some(0x8000), ""),
+                                      ("loader", BlockKind::Basic, None, "LINE 10"),
+                                      ("", BlockKind::Data, None, "flag FFH")]);
+        assert_eq!(blocks[0].data, [0x3e, 0x01, 0xc9]);
+        assert_eq!(blocks[2].data, [0x3e, 0x01, 0xc9]);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut code = standard(0xff, &[0x3e, 0x01, 0xc9]);
+        code[1] ^= 0x01;
+        let blocks = tap_blocks(&tap(&[header(3, b"game      ", 3, 0x8000), code.clone(), code])).unwrap();
+        assert_eq!(blocks[0].note, "checksum error");
+        assert_eq!(blocks[1].note, "checksum error, flag FFH");
+    }
+
+    // ヘッダより短いデータと、途中で切れたファイル
+    #[test]
+    fn truncated() {
+        let blocks = tap_blocks(&tap(&[header(3, b"game      ", 5, 0x8000), standard(0xff, &[0x3e, 0x01, 0xc9])])).unwrap();
+        assert_eq!(blocks[0].note, "2 bytes missing");
+
+        let data = tap(&[header(3, b"game      ", 3, 0x8000)]);
+        assert_eq!(format_error(tap_blocks(&data[..data.len() - 1])), "TAP: block at 0H is truncated");
+    }
+
+    fn tzx(blocks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut data = TZX_ID.to_vec();
+        data.extend([1, 20]);
+        for (id, block) in blocks {
+            data.push(*id);
+            data.extend(block);
+        }
+        data
+    }
+
+    // 10H 標準  11H ターボ  14H データだけ  30H 説明 (飛ばす)
+    fn tzx_tape() -> Vec<u8> {
+        let standard_block = |block: Vec<u8>| {
+            let mut b = vec![0xe8, 0x03];
+            b.extend((block.len() as u16).to_le_bytes());
+            b.extend(block);
+            b
+        };
+        let mut turbo = vec![0x00; 15];
+        let code = standard(0xff, &[0x3e, 0x01, 0xc9]);
+        turbo.extend(&(code.len() as u32).to_le_bytes()[..3]);
+        turbo.extend(&code);
+        let mut pure = vec![0x00; 7];
+        pure.extend([2, 0, 0, 0x12, 0x34]);
+        tzx(&[(0x30, b"\x04text".to_vec()),
+              (0x10, standard_block(header(3, b"game      ", 3, 0x8000))),
+              (0x11, turbo),
+              (0x14, pure)])
+    }
+
+    #[test]
+    fn tzx_files() {
+        let blocks = tzx_blocks(&tzx_tape()).unwrap();
+        assert_eq!(summary(&blocks), [("game", BlockKind::Machine, Some(0x8000), "turbo"),
+                                      ("", BlockKind::Data, None, "pure data")]);
+        assert_eq!(blocks[1].data, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn tzx_errors() {
+        let data = tzx_tape();
+        assert_eq!(format_error(tzx_blocks(&data[..9])), "TZX: no \"ZXTape!\" header");
+        assert_eq!(format_error(tzx_blocks(&data[..data.len() - 1])), "TZX: block 14H at 40H is truncated");
+        assert_eq!(format_error(tzx_blocks(&data[..data.len() - 12])), "TZX: block 14H at 40H is truncated");
+        assert_eq!(format_error(tzx_blocks(&tzx(&[(0x99, Vec::new())]))), "TZX: unknown block 99H at AH");
+    }
+}
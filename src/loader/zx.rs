@@ -0,0 +1,367 @@
+/*   Z80 Disassembler
+     name is loader/zx.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use super::{Block, BlockKind, Image, LoadError, LoadOptions, Select};
+
+// **********************************************
+//      ZX Spectrum のスナップショット (.sna / .z80)
+//
+//      RAM は 4000H-FFFFH  128K の機種は 16K のバンクが 8 つあって、
+//      4000H にバンク 5、8000H にバンク 2、C000H に 7FFDH ポートで選んだバンクが見える
+//      バンクをブロックにして、見えているものを置く  -n で別のバンクを選べる
+//      実行開始アドレスは PC (48K の .sna はスタックに積まれた戻りアドレス)
+// **********************************************
+
+const BANK_SIZE: usize = 0x4000;
+const SNA_HEADER: usize = 27;
+const RAM_48K: usize = 3 * BANK_SIZE;
+
+// バンクの置かれるアドレス
+fn bank_address(bank: usize) -> u16 {
+    match bank {
+        5 => 0x4000,
+        2 => 0x8000,
+        _ => 0xc000,
+    }
+}
+
+// バンクのブロック  PC が中にあれば実行開始アドレスにする
+fn bank_block(name: String, address: u16, data: Vec<u8>, pc: u16, paged: bool) -> Block {
+    let exec = (paged && (address..=address.wrapping_add(data.len() as u16 - 1)).contains(&pc)).then_some(pc);
+    let note = if paged { String::from("paged in") } else { String::new() };
+    Block{ name, kind: BlockKind::Data, address: Some(address), exec, data, note }
+}
+
+// **********************************************
+//      見えているバンクを置く (-n で選んだ時は、そのブロック)
+// **********************************************
+fn paged_image(blocks: Vec<Block>, options: &LoadOptions) -> Result<Image, LoadError> {
+    if options.select.is_some() {
+        return Image::from_blocks(blocks, options);
+    }
+    let paged: Vec<Block> = blocks.iter().filter(|b| !b.note.is_empty()).cloned().collect();
+    let mut image = Image::from_blocks(paged, &LoadOptions{ select: Some(Select::All), ..options.clone() })?;
+    image.blocks = blocks;
+    Ok(image)
+}
+
+// **********************************************
+//      .sna
+//          ヘッダ 27 バイト (+17H SP)  + 4000H-FFFFH の 48K
+//      128K の時は、そのうしろに PC(2) 7FFDH(1) TR-DOS(1) と、残りのバンク
+//      48K の時は、PC はスタックに積まれている
+// **********************************************
+pub fn sna(data: &[u8], options: &LoadOptions) -> Result<Image, LoadError> {
+    if data.len() < SNA_HEADER + RAM_48K {
+        return Err(LoadError::Format(format!("SNA: file is too short ({} bytes)", data.len())));
+    }
+    let sp = u16::from_le_bytes([data[23], data[24]]);
+    let ram = &data[SNA_HEADER..SNA_HEADER + RAM_48K];
+    let rest = &data[SNA_HEADER + RAM_48K..];
+
+    if rest.len() < 4 {
+        // ----- 48K  PC をスタックから取る -----
+        let byte = |a: u16| if a >= 0x4000 { ram[a as usize - 0x4000] } else { 0 };
+        let pc = u16::from_le_bytes([byte(sp), byte(sp.wrapping_add(1))]);
+        let block = bank_block(String::from("RAM"), 0x4000, ram.to_vec(), pc, true);
+        return paged_image(vec![block], options);
+    }
+
+    // ----- 128K -----
+    let pc = u16::from_le_bytes([rest[0], rest[1]]);
+    let current = (rest[2] & 0x07) as usize;
+    // バンク 2 / 5 が C000H にも見えている時は、C000H にも置く
+    let mut banks: Vec<(usize, u16, &[u8])> = vec![(5, 0x4000, &ram[..BANK_SIZE]),
+                                                   (2, 0x8000, &ram[BANK_SIZE..2 * BANK_SIZE]),
+                                                   (current, 0xc000, &ram[2 * BANK_SIZE..])];
+    let others = (0..8).filter(|b| ![5, 2, current].contains(b));
+    for (bank, chunk) in others.zip(rest[4..].chunks_exact(BANK_SIZE)) {
+        banks.push((bank, bank_address(bank), chunk));
+    }
+    banks.sort_by_key(|(bank, address, _)| (*address, *bank));     // -n の番号はアドレス順
+    let blocks = banks.into_iter().map(|(bank, address, chunk)| {
+        let paged = bank == 5 || bank == 2 || bank == current;
+        bank_block(format!("bank {}", bank), address, chunk.to_vec(), pc, paged)
+    }).collect();
+    paged_image(blocks, options)
+}
+
+// **********************************************
+//      .z80
+//      バージョン 1  ヘッダ 30 バイト (+06H PC) + 48K (+0CH のビット 5 が立っていれば圧縮)
+//      バージョン 2 / 3  +06H の PC が 0 で、+1EH に追加ヘッダの長さ (23 / 54 / 55)
+//          +20H PC  +22H 機種  +23H 7FFDH ポートの値
+//          そのうしろに メモリのブロック  長さ(2) ページ(1) データ  (長さ FFFFH は圧縮なし)
+//          ページ  48K は 8 -> 4000H  4 -> 8000H  5 -> C000H、128K は 3-10 がバンク 0-7
+//      圧縮は ED ED 個数 値 の繰り返し
+// **********************************************
+pub fn z80(data: &[u8], options: &LoadOptions) -> Result<Image, LoadError> {
+    let error = |message: String| LoadError::Format(format!("Z80: {}", message));
+    if data.len() < 30 {
+        return Err(error(String::from("header is truncated")));
+    }
+    let word = |p: usize| u16::from_le_bytes([data[p], data[p + 1]]);
+    let pc = word(6);
+
+    // ----- バージョン 1 -----
+    if pc != 0 {
+        let body = &data[30..];
+        let compressed = data[12] != 0xff && data[12] & 0x20 != 0;
+        let ram = if compressed {
+            let end = body.windows(4).rposition(|w| w == [0x00, 0xed, 0xed, 0x00]).unwrap_or(body.len());
+            decompress(&body[..end], RAM_48K).map_err(error)?
+        } else {
+            body.get(..RAM_48K).ok_or_else(|| error(String::from("memory is truncated")))?.to_vec()
+        };
+        let block = bank_block(String::from("RAM"), 0x4000, ram, pc, true);
+        return paged_image(vec![block], options);
+    }
+
+    // ----- バージョン 2 / 3 -----
+    if data.len() < 32 {
+        return Err(error(String::from("header is truncated")));
+    }
+    let extra = word(30) as usize;
+    if data.len() < 32 + extra || extra < 4 {
+        return Err(error(String::from("extra header is truncated")));
+    }
+    let pc = word(32);
+    let mode = data[34];
+    let is_128k = if extra == 23 { matches!(mode, 3 | 4) } else { matches!(mode, 4..=7 | 9 | 10 | 12 | 13) };
+    let current = if is_128k { (data[35] & 0x07) as usize } else { 0 };
+
+    let mut blocks = Vec::new();     // (ページ, ブロック)
+    let mut pos = 32 + extra;
+    while pos + 3 <= data.len() {
+        let length = word(pos);
+        let page = data[pos + 2] as usize;
+        pos += 3;
+        let (size, memory) = if length == 0xffff {
+            (BANK_SIZE, data.get(pos..pos + BANK_SIZE).map(|d| Ok(d.to_vec())))
+        } else {
+            let size = length as usize;
+            (size, data.get(pos..pos + size).map(|d| decompress(d, BANK_SIZE)))
+        };
+        let memory = match memory {
+            Some(Ok(memory)) => memory,
+            Some(Err(e))     => return Err(error(format!("page {}: {}", page, e))),
+            None             => return Err(error(format!("page {} is truncated", page))),
+        };
+        pos += size;
+
+        let block = if is_128k {
+            let Some(bank) = page.checked_sub(3).filter(|b| *b < 8) else {
+                continue;           // ROM など
+            };
+            if bank == current && bank_address(bank) != 0xc000 {   // バンク 2 / 5 が C000H にも見えている
+                blocks.push((page, bank_block(format!("bank {}", bank), 0xc000, memory.clone(), pc, true)));
+            }
+            let paged = bank == 5 || bank == 2 || bank == current;
+            let address = bank_address(bank);
+            bank_block(format!("bank {}", bank), address, memory, pc, paged)
+        } else {
+            let address = match page {
+                8 => 0x4000,
+                4 => 0x8000,
+                5 => 0xc000,
+                _ => continue,
+            };
+            bank_block(format!("page {}", page), address, memory, pc, true)
+        };
+        blocks.push((page, block));
+    }
+    blocks.sort_by_key(|(page, b)| (b.address, *page));     // -n の番号はアドレス順 (同じならバンクの番号順)
+    paged_image(blocks.into_iter().map(|(_, b)| b).collect(), options)
+}
+
+// ED ED 個数 値 を展開する  size バイトになること
+fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut memory = Vec::with_capacity(size);
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(&[0xed, 0xed]) && i + 3 < data.len() {
+            memory.extend(std::iter::repeat_n(data[i + 3], data[i + 2] as usize));
+            i += 4;
+        } else {
+            memory.push(data[i]);
+            i += 1;
+        }
+    }
+    if memory.len() != size {
+        return Err(format!("decompressed to {} bytes (expected {})", memory.len(), size));
+    }
+    Ok(memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::tests::format_error;
+
+    // SP が 8000H を指していて、そこに 9000H が積まれた 48K の .sna
+    fn sna_48k() -> Vec<u8> {
+        let mut data = vec![0x00; SNA_HEADER + RAM_48K];
+        data[23..25].copy_from_slice(&0x8000u16.to_le_bytes());
+        data[SNA_HEADER + 0x4000..][..2].copy_from_slice(&0x9000u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn sna_48k_pc_from_stack() {
+        let image = sna(&sna_48k(), &LoadOptions::default()).unwrap();
+        assert_eq!((image.segments[0].address, image.segments[0].data.len()), (0x4000, RAM_48K));
+        assert_eq!(image.entry_points, [0x9000]);
+    }
+
+    // バンク n は n で埋める  C000H にはバンク 1
+    #[test]
+    fn sna_128k_banks() {
+        let mut data = vec![0x00; SNA_HEADER];
+        for bank in [5, 2, 1] {
+            data.extend([bank; BANK_SIZE]);
+        }
+        data.extend([0x00, 0xc0, 0x01, 0x00]);
+        for bank in [0, 3, 4, 6, 7] {
+            data.extend([bank; BANK_SIZE]);
+        }
+        let image = sna(&data, &LoadOptions::default()).unwrap();
+        let names: Vec<&str> = image.blocks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["bank 5", "bank 2", "bank 0", "bank 1", "bank 3", "bank 4", "bank 6", "bank 7"]);
+        assert!(image.blocks.iter().all(|b| b.data[0] as usize == b.name[5..].parse::<usize>().unwrap()));
+        assert_eq!(image.entry_points, [0xc000]);
+        assert_eq!(image.byte(0xc000), Some(1));
+
+        // -n で見えていないバンクを選ぶ
+        let options = LoadOptions{ select: Some(Select::Number(5)), ..Default::default() };
+        assert_eq!(sna(&data, &options).unwrap().byte(0xc000), Some(3));
+    }
+
+    // バンク 5 が C000H にも見えている時は、両方に置く
+    #[test]
+    fn sna_128k_bank5_paged() {
+        let mut data = vec![0x00; SNA_HEADER];
+        for bank in [5, 2, 5] {
+            data.extend([bank; BANK_SIZE]);
+        }
+        data.extend([0x00, 0xc0, 0x05, 0x00]);
+        for bank in [0, 1, 3, 4, 6, 7] {
+            data.extend([bank; BANK_SIZE]);
+        }
+        let image = sna(&data, &LoadOptions::default()).unwrap();
+        let blocks: Vec<(&str, Option<u16>)> = image.blocks.iter().map(|b| (b.name.as_str(), b.address)).collect();
+        assert_eq!(blocks[..3], [("bank 5", Some(0x4000)), ("bank 2", Some(0x8000)), ("bank 0", Some(0xc000))]);
+        assert_eq!(blocks[6], ("bank 5", Some(0xc000)));
+        assert_eq!((image.byte(0x4000), image.byte(0xc000)), (Some(5), Some(5)));
+        assert_eq!(image.entry_points, [0xc000]);
+    }
+
+    #[test]
+    fn sna_truncated() {
+        assert_eq!(format_error(sna(&sna_48k()[..100], &LoadOptions::default())), "SNA: file is too short (100 bytes)");
+    }
+
+    // 先頭が C9H で、あとは 0 の 48K を圧縮したもの
+    fn compressed_48k() -> Vec<u8> {
+        let mut body = vec![0xc9];
+        for _ in 0..192 {
+            body.extend([0xed, 0xed, 0xff, 0x00]);
+        }
+        body.extend([0xed, 0xed, 0xbf, 0x00]);
+        body
+    }
+
+    #[test]
+    fn z80_version1() {
+        let mut data = vec![0x00; 30];
+        data[6..8].copy_from_slice(&0x4000u16.to_le_bytes());
+        data[12] = 0x20;
+        data.extend(compressed_48k());
+        data.extend([0x00, 0xed, 0xed, 0x00]);
+        let image = z80(&data, &LoadOptions::default()).unwrap();
+        assert_eq!((image.segments[0].address, image.segments[0].data.len()), (0x4000, RAM_48K));
+        assert_eq!((image.byte(0x4000), image.byte(0x4001)), (Some(0xc9), Some(0x00)));
+        assert_eq!(image.entry_points, [0x4000]);
+
+        data.drain(31..35);             // 255 バイト足りない
+        assert_eq!(format_error(z80(&data, &LoadOptions::default())), "Z80: decompressed to 48897 bytes (expected 49152)");
+        data[12] = 0x00;                // 圧縮なし
+        assert_eq!(format_error(z80(&data, &LoadOptions::default())), "Z80: memory is truncated");
+    }
+
+    // バージョン 3 の 48K  ページ 4 (8000H) は圧縮、ページ 8 (4000H) は圧縮なし
+    fn z80_version3() -> Vec<u8> {
+        let mut data = vec![0x00; 32 + 54];
+        data[30..32].copy_from_slice(&54u16.to_le_bytes());
+        data[32..34].copy_from_slice(&0x8000u16.to_le_bytes());
+        let mut page4 = vec![0xc9];
+        for _ in 0..64 {
+            page4.extend([0xed, 0xed, 0xff, 0x00]);
+        }
+        page4.extend([0xed, 0xed, 0x3f, 0x00]);
+        data.extend((page4.len() as u16).to_le_bytes());
+        data.push(4);
+        data.extend(page4);
+        data.extend([0xff, 0xff, 8]);
+        data.extend([0x11; BANK_SIZE]);
+        data
+    }
+
+    #[test]
+    fn z80_pages() {
+        let image = z80(&z80_version3(), &LoadOptions::default()).unwrap();
+        let blocks: Vec<(&str, Option<u16>)> = image.blocks.iter().map(|b| (b.name.as_str(), b.address)).collect();
+        assert_eq!(blocks, [("page 8", Some(0x4000)), ("page 4", Some(0x8000))]);
+        assert_eq!((image.byte(0x4000), image.byte(0x8000), image.byte(0xc000)), (Some(0x11), Some(0xc9), None));
+        assert_eq!(image.entry_points, [0x8000]);
+    }
+
+    // 128K でバンク 5 (ページ 8) が C000H にも見えている
+    #[test]
+    fn z80_128k_bank5_paged() {
+        let mut data = vec![0x00; 32 + 54];
+        data[30..32].copy_from_slice(&54u16.to_le_bytes());
+        data[32..34].copy_from_slice(&0xc000u16.to_le_bytes());
+        data[34] = 4;                   // 128K
+        data[35] = 5;                   // 7FFDH  C000H にバンク 5
+        data.extend([0xff, 0xff, 8]);
+        data.extend([0x55; BANK_SIZE]);
+        let image = z80(&data, &LoadOptions::default()).unwrap();
+        let blocks: Vec<(&str, Option<u16>)> = image.blocks.iter().map(|b| (b.name.as_str(), b.address)).collect();
+        assert_eq!(blocks, [("bank 5", Some(0x4000)), ("bank 5", Some(0xc000))]);
+        assert_eq!((image.byte(0x4000), image.byte(0xc000)), (Some(0x55), Some(0x55)));
+        assert_eq!(image.entry_points, [0xc000]);
+    }
+
+    #[test]
+    fn z80_truncated() {
+        let data = z80_version3();
+        let options = LoadOptions::default();
+        assert_eq!(format_error(z80(&data[..29], &options)), "Z80: header is truncated");
+        assert_eq!(format_error(z80(&data[..31], &options)), "Z80: header is truncated");
+        assert_eq!(format_error(z80(&data[..40], &options)), "Z80: extra header is truncated");
+        assert_eq!(format_error(z80(&data[..data.len() - 1], &options)), "Z80: page 8 is truncated");
+    }
+}
@@ -0,0 +1,333 @@
+/*   Z80 Disassembler
+     name is source.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::prelude::*;
+
+use crate::charcode::Charset;
+use crate::encode::is_canonical;
+use crate::format::{format_byte, format_data_items, format_instruction, format_word, Syntax};
+use crate::instruction::{Instruction, Mnemonic, Operand, Reg};
+use crate::label::Labels;
+use crate::line::{DataKind, Line};
+
+const INDENT: &str = "        ";
+
+// **********************************************
+//      アセンブラの種類
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Sjasmplus,      // sjasmplus        ローカルラベルは .L1234
+    Pasmo,          // pasmo            ローカルラベルは PROC / LOCAL / ENDP
+    Z80asm,         // z88dk の z80asm  DEFB / DEFW / DEFS / DEFM
+    M80,            // Microsoft M80    .Z80 / ASEG / END、文字列は '...'
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name.to_ascii_lowercase().as_str() {
+            "sjasmplus" => Some(Dialect::Sjasmplus),
+            "pasmo"     => Some(Dialect::Pasmo),
+            "z80asm"    => Some(Dialect::Z80asm),
+            "m80"       => Some(Dialect::M80),
+            _           => None,
+        }
+    }
+
+    // **********************************************
+    //      未定義命令を、ニーモニックで書けるか？
+    //      書けないものは DB にする
+    // **********************************************
+    fn supports(self, inst: &Instruction) -> bool {
+        if !inst.undocumented {
+            return true;
+        }
+        let index_half = inst.operands.iter().any(|o| matches!(o, Operand::Reg(Reg::IXH | Reg::IXL | Reg::IYH | Reg::IYL)));
+        let copy = inst.operands.iter().any(|o| matches!(o, Operand::Indexed(..)))
+                && inst.operands.iter().any(|o| matches!(o, Operand::Reg(_)));   // RLC (IX+d),B など
+        let sll = inst.mnemonic == Mnemonic::Sll;
+        let in_f = inst.operands.first() == Some(&Operand::Reg(Reg::F));
+        let out_c0 = inst.mnemonic == Mnemonic::Out && inst.operands.last() == Some(&Operand::Imm8(0));
+        match self {
+            Dialect::Sjasmplus => index_half || (sll && !copy) || in_f || out_c0,
+            Dialect::Pasmo     => index_half || (sll && !copy),
+            Dialect::Z80asm    => index_half,
+            Dialect::M80       => false,
+        }
+    }
+
+    // DB / DW / DS / 文字列 の疑似命令
+    fn directive(self, kind: DataKind) -> &'static str {
+        match (self, kind) {
+            (Dialect::Z80asm, DataKind::Words | DataKind::Pointers) => "DEFW",
+            (Dialect::Z80asm, DataKind::Fill)                       => "DEFS",
+            (Dialect::Z80asm, DataKind::Text)                       => "DEFM",
+            (Dialect::Z80asm, _)                                    => "DEFB",
+            (_, DataKind::Words | DataKind::Pointers)               => "DW",
+            (_, DataKind::Fill)                                     => "DS",
+            _                                                       => "DB",
+        }
+    }
+
+    // 文字列をくくる文字
+    fn quote(self) -> char {
+        if self == Dialect::M80 { '\'' } else { '"' }
+    }
+}
+
+// **********************************************
+//      ローカルラベルにできるアドレスを探す
+//      自動でつけたラベルのうち、CALL / RST / アドレスの表から参照されず、
+//      直前のグローバルラベルの範囲の中からしか飛んでこないもの
+// **********************************************
+fn local_labels(lines: &[Line], labels: &Labels) -> BTreeSet<u16> {
+    let mut global: BTreeSet<u16> = labels.iter()
+                                          .map(|(a, _)| a)
+                                          .filter(|a| !labels.is_auto(*a))
+                                          .collect();
+    for line in lines {
+        match line {
+            Line::Code(inst) if inst.is_call() => global.extend(inst.target()),
+            Line::Data { .. }                  => global.extend(line.references()),
+            _ => {},
+        }
+    }
+    let named: BTreeSet<u16> = labels.iter().map(|(a, _)| a).collect();    // EQU の名前は入れない
+    global.retain(|a| named.contains(a));
+
+    // グローバルになるラベルが増えなくなるまで繰り返す
+    loop {
+        let scope = |address: u16| global.range(..=address).next_back().copied();
+        let mut promote = BTreeSet::new();
+        for (address, _) in labels.iter() {
+            if !global.contains(&address) && scope(address).is_none() {
+                promote.insert(address);    // 前にグローバルラベルがない
+            }
+        }
+        for line in lines {
+            for target in line.references() {
+                if named.contains(&target) && !global.contains(&target)
+                   && scope(line.address()) != scope(target) {
+                    promote.insert(target);     // 別の範囲から飛んでくる
+                }
+            }
+        }
+        if promote.is_empty() {
+            break;
+        }
+        global.extend(promote);
+    }
+    labels.iter().map(|(a, _)| a).filter(|a| !global.contains(a)).collect()
+}
+
+// **********************************************
+//      アセンブルし直せるソースを出力する
+//      lines は org から隙間なく並んでいること (Skip があれば ORG をつけ直す)
+//      comments は行のアドレスにつける注釈
+// **********************************************
+pub fn write_source<W: Write>(out: &mut W, org: u16, lines: &[Line], labels: Option<&Labels>,
+                              comments: &BTreeMap<u16, String>, dialect: Dialect) -> io::Result<()> {
+    for (_, text) in source_lines(org, lines, labels, comments, dialect) {
+        writeln!(out, "{}", text)?;
+    }
+    Ok(())
+}
+
+// **********************************************
+//      ソースの行を作る
+//      それぞれの行に、もとになった lines の番号をつける (ORG や EQU などは None)
+// **********************************************
+pub(crate) fn source_lines(org: u16, lines: &[Line], labels: Option<&Labels>,
+                           comments: &BTreeMap<u16, String>, dialect: Dialect) -> Vec<(Option<usize>, String)> {
+    let mut out: Vec<(Option<usize>, String)> = Vec::new();
+
+    // ----- ローカルラベル (sjasmplus と pasmo だけ) --------
+    let locals = match labels {
+        Some(labels) if matches!(dialect, Dialect::Sjasmplus | Dialect::Pasmo) => local_labels(lines, labels),
+        _ => BTreeSet::new(),
+    };
+    let mut labels = labels.cloned();
+    let mut scopes: BTreeMap<u16, Vec<String>> = BTreeMap::new();     // グローバルラベル -> その中のローカルラベル
+    if let Some(labels) = labels.as_mut() {
+        for address in &locals {
+            let name = labels.get(*address).unwrap_or_default().to_string();
+            let owner = labels.iter().map(|(a, _)| a)
+                              .filter(|a| *a <= *address && !locals.contains(a))
+                              .last();
+            if let Some(owner) = owner {
+                scopes.entry(owner).or_default().push(name.clone());
+            }
+            if dialect == Dialect::Sjasmplus {
+                labels.add(*address, &format!(".{}", name));
+            }
+        }
+    }
+    let labels = labels.as_ref();
+
+    // ----- 先頭 --------
+    if dialect == Dialect::M80 {
+        out.push((None, format!("{}.Z80", INDENT)));
+        out.push((None, format!("{}ASEG", INDENT)));
+    }
+    for (address, name) in labels.iter().flat_map(|l| l.symbols()) {    // 範囲の外の名前
+        out.push((None, format!("{:<7} EQU   {}", name, format_word(address))));
+    }
+    out.push((None, format!("{}ORG   {}", INDENT, format_word(org))));
+
+    let mut in_proc = false;
+    for (i, line) in lines.iter().enumerate() {
+        // ----- ラベル --------
+        if let Some(name) = labels.and_then(|l| l.get(line.address())) {
+            let global = !locals.contains(&line.address());
+            if dialect == Dialect::Pasmo && global && in_proc {
+                out.push((Some(i), format!("{}ENDP", INDENT)));
+                in_proc = false;
+            }
+            out.push((Some(i), format!("{}:", name)));
+            if dialect == Dialect::Pasmo && global {
+                if let Some(names) = scopes.get(&line.address()) {
+                    out.push((Some(i), format!("{}PROC", INDENT)));
+                    out.push((Some(i), format!("{}LOCAL {}", INDENT, names.join(","))));
+                    in_proc = true;
+                }
+            }
+        }
+
+        // ----- 命令・データ --------
+        let text = match line {
+            Line::Code(inst) if dialect.supports(inst) && is_canonical(inst) => {
+                let text = format_instruction(inst, labels, Syntax::Source);
+                match comments.get(&inst.address) {
+                    Some(comment) => format!("{}{:<24}; {}", INDENT, text, comment),
+                    None          => format!("{}{}", INDENT, text),
+                }
+            },
+            Line::Code(inst) => {   // アセンブラが同じバイト列にしてくれない命令は DB で書く
+                format!("{:<32}; {}", bytes_line(&inst.bytes, dialect), format_instruction(inst, None, Syntax::Source))
+            },
+            Line::Data { bytes, kind, .. } => {
+                let items = format_data_items(bytes, *kind, labels, Charset::Ascii, dialect.quote());
+                format!("{}{:<6}{}", INDENT, dialect.directive(*kind), items.join(","))
+            },
+            Line::Skip { address, size } => {
+                if i + 1 < lines.len() {
+                    out.push((None, format!("{}ORG   {}", INDENT, format_word(address.wrapping_add(*size as u16)))));
+                }
+                continue;
+            },
+        };
+        out.push((Some(i), text));
+    }
+
+    // ----- 最後 --------
+    if in_proc {
+        out.push((None, format!("{}ENDP", INDENT)));
+    }
+    if matches!(dialect, Dialect::Pasmo | Dialect::M80) {
+        out.push((None, format!("{}END", INDENT)));
+    }
+    out
+}
+
+// **********************************************
+//      バイト列を DB で書いた行
+// **********************************************
+pub(crate) fn bytes_line(bytes: &[u8], dialect: Dialect) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format_byte(*b)).collect();
+    format!("{}{:<6}{}", INDENT, dialect.directive(DataKind::Bytes), bytes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::assemble;
+    use crate::control::{Control, Region, RegionKind};
+    use crate::Disassemble;
+
+    const ORG: u16 = 0x8000;
+
+    // 命令とデータのまざったプログラム
+    fn program() -> Vec<u8> {
+        let mut data = vec![
+            0x21, 0x20, 0x80,       // 8000  LD    HL,8020H
+            0xdd, 0x94,             // 8003  SUB   IXH          未定義命令
+            0xed, 0x70,             // 8005  IN    F,(C)        未定義命令
+            0x10, 0xfa,             // 8007  DJNZ  8003H
+            0xcd, 0x0e, 0x80,       // 8009  CALL  800EH
+            0x18, 0xfe,             // 800C  JR    800CH
+            0xc9,                   // 800E  RET
+            0xed, 0x4c,             // 800F  NEG のミラー       DB で出す
+            0xdd, 0x7e, 0xfb,       // 8011  LD    A,(IX-5D)
+            0xfd, 0xcb, 0x05, 0x00, // 8014  RLC   (IY+5D),B    未定義命令
+        ];
+        data.resize(0x20, 0x00);
+        data.extend(b"HELLO\0");    // 8020  文字列
+        data.extend([0x00, 0x80]);  // 8026  アドレスの表
+        data
+    }
+
+    fn disassemble(data: &[u8]) -> Disassemble {
+        let mut dasm = Disassemble::new(data.to_vec(), ORG.into());
+        dasm.set_control(Control {
+            regions: vec![
+                Region { start: 0x8020, end: 0x8025, kind: RegionKind::Text },
+                Region { start: 0x8026, end: 0x8027, kind: RegionKind::Pointers },
+            ],
+            ..Control::default()
+        });
+        dasm.disassemble_all();
+        dasm.make_labels("L");
+        dasm
+    }
+
+    // どのアセンブラ向けのソースも、アセンブルし直すと同じバイナリになる
+    #[test]
+    fn round_trip_each_dialect() {
+        let data = program();
+        let dasm = disassemble(&data);
+        for dialect in [Dialect::Sjasmplus, Dialect::Pasmo, Dialect::Z80asm, Dialect::M80] {
+            let mut out = Vec::new();
+            dasm.output_source(&mut out, dialect).unwrap();
+            let source = String::from_utf8(out).unwrap();
+            let program = assemble(&source).unwrap_or_else(|e| panic!("{:?}: {}\n{}", dialect, e, source));
+            assert_eq!(program.origin, ORG, "{:?}", dialect);
+            assert_eq!(program.bytes, data, "{:?}\n{}", dialect, source);
+        }
+    }
+
+    // 戻せない命令は DB で書く
+    #[test]
+    fn non_canonical_as_bytes() {
+        let dasm = disassemble(&program());
+        let mut out = Vec::new();
+        dasm.output_source(&mut out, Dialect::Z80asm).unwrap();
+        let source = String::from_utf8(out).unwrap();
+        assert!(source.contains("DEFB  0EDH,4CH          ; NEG"), "{}", source);
+        assert!(!source.contains("        NEG"), "{}", source);
+    }
+}
@@ -0,0 +1,241 @@
+/*   Z80 Disassembler
+     name is system/cpm.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::flow::falls_through;
+use crate::instruction::{Instruction, Mnemonic, Operand, Reg, RegPair};
+use crate::label::SymbolKind;
+use crate::line::Line;
+
+// **********************************************
+//      CP/M
+//      .COM は TPA の先頭 0100H に読み込まれて、そこから実行される
+//      BDOS は C に機能番号を入れて CALL 0005H で呼ぶ
+// **********************************************
+
+pub const TPA: u16 = 0x0100;
+const BDOS: u16 = 0x0005;
+
+// ページ 0 の決まっているアドレス
+pub const SYMBOLS: &[(u16, &str, SymbolKind)] = &[
+    (0x0000, "WBOOT",  SymbolKind::Code),   // ウォームブート
+    (0x0003, "IOBYTE", SymbolKind::Data),   // I/O バイト
+    (0x0004, "CDISK",  SymbolKind::Data),   // カレントドライブとユーザー番号
+    (0x0005, "BDOS",   SymbolKind::Code),   // BDOS の入り口
+    (0x005c, "FCB",    SymbolKind::Data),   // コマンドラインの１番目のファイル名の FCB
+    (0x006c, "FCB2",   SymbolKind::Data),   // ２番目のファイル名
+    (0x0080, "DMA",    SymbolKind::Data),   // DMA バッファ (最初はコマンドラインの残り)
+];
+
+// **********************************************
+//      BDOS の機能番号の名前 (CP/M 3 の名前、7 / 8 は CP/M 2.2 の機能)
+// **********************************************
+fn bdos_name(function: u8) -> Option<&'static str> {
+    let name = match function {
+        0   => "P_TERMCPM",
+        1   => "C_READ",
+        2   => "C_WRITE",
+        3   => "A_READ",
+        4   => "A_WRITE",
+        5   => "L_WRITE",
+        6   => "C_RAWIO",
+        7   => "GET_IOBYTE",
+        8   => "SET_IOBYTE",
+        9   => "C_WRITESTR",
+        10  => "C_READSTR",
+        11  => "C_STAT",
+        12  => "S_BDOSVER",
+        13  => "DRV_ALLRESET",
+        14  => "DRV_SET",
+        15  => "F_OPEN",
+        16  => "F_CLOSE",
+        17  => "F_SFIRST",
+        18  => "F_SNEXT",
+        19  => "F_DELETE",
+        20  => "F_READ",
+        21  => "F_WRITE",
+        22  => "F_MAKE",
+        23  => "F_RENAME",
+        24  => "DRV_LOGINVEC",
+        25  => "DRV_GET",
+        26  => "F_DMAOFF",
+        27  => "DRV_ALLOCVEC",
+        28  => "DRV_SETRO",
+        29  => "DRV_ROVEC",
+        30  => "F_ATTRIB",
+        31  => "DRV_DPB",
+        32  => "F_USERNUM",
+        33  => "F_READRAND",
+        34  => "F_WRITERAND",
+        35  => "F_SIZE",
+        36  => "F_RANDREC",
+        37  => "DRV_RESET",
+        38  => "DRV_ACCESS",
+        39  => "DRV_FREE",
+        40  => "F_WRITEZF",
+        41  => "F_TESTWRITE",
+        42  => "F_LOCK",
+        43  => "F_UNLOCK",
+        44  => "F_MULTISEC",
+        45  => "F_ERRMODE",
+        46  => "DRV_SPACE",
+        47  => "P_CHAIN",
+        48  => "DRV_FLUSH",
+        49  => "S_SCB",
+        50  => "S_BIOS",
+        59  => "P_LOAD",
+        60  => "S_RSX",
+        99  => "F_TRUNCATE",
+        100 => "DRV_SETLABEL",
+        101 => "DRV_GETLABEL",
+        102 => "F_TIMEDATE",
+        103 => "F_WRITEXFCB",
+        104 => "T_SET",
+        105 => "T_GET",
+        106 => "F_PASSWD",
+        107 => "S_SERIAL",
+        108 => "P_CODE",
+        109 => "C_MODE",
+        110 => "C_DELIMIT",
+        111 => "C_WRITEBLK",
+        112 => "L_WRITEBLK",
+        152 => "F_PARSE",
+        _   => return None,
+    };
+    Some(name)
+}
+
+// **********************************************
+//      CALL 0005H / JP 0005H に BDOS の機能の名前をつける
+//      機能番号は、その前の LD C,n (LD BC,nn) から探す
+//      C を書き換える命令や、実行が続かない命令の後ろでは分からなくなる
+// **********************************************
+pub fn bdos_comments(lines: &[Line]) -> BTreeMap<u16, String> {
+    let mut comments = BTreeMap::new();
+    let mut function: Option<u8> = None;
+    for line in lines {
+        let Some(inst) = line.instruction() else {
+            function = None;                // データをはさんだら分からない
+            continue;
+        };
+        match (inst.mnemonic, inst.operands.as_slice()) {
+            (Mnemonic::Ld, [Operand::Reg(Reg::C), Operand::Imm8(n)]) => function = Some(*n),
+            (Mnemonic::Ld, [Operand::RegPair(RegPair::BC), Operand::Imm16(n)]) => function = Some(*n as u8),
+            (Mnemonic::Call | Mnemonic::Jp, ..) if inst.target() == Some(BDOS) => {
+                let comment = match function {
+                    Some(n) => match bdos_name(n) {
+                        Some(name) => name.to_string(),
+                        None       => format!("BDOS function {}", n),
+                    },
+                    None => String::from("BDOS (function unknown)"),
+                };
+                comments.insert(inst.address, comment);
+                function = None;            // BDOS は C を壊す
+            },
+            _ if writes(inst, &C) || !falls_through(inst) => function = None,
+            _ => {},
+        }
+    }
+    comments
+}
+
+// **********************************************
+//      BDOS に渡すアドレス (FCB や文字列) を入れる LD DE,nn を探す
+//      CALL 0005H / JP 0005H までに DE が書き換えられないものだけ
+// **********************************************
+pub fn bdos_arguments(lines: &[Line]) -> BTreeSet<u16> {
+    let mut arguments = BTreeSet::new();
+    let mut load: Option<u16> = None;      // LD DE,nn のアドレス
+    for line in lines {
+        let Some(inst) = line.instruction() else {
+            load = None;
+            continue;
+        };
+        match (inst.mnemonic, inst.operands.as_slice()) {
+            (Mnemonic::Ld, [Operand::RegPair(RegPair::DE), Operand::Imm16(_)]) => load = Some(inst.address),
+            (Mnemonic::Call | Mnemonic::Jp, ..) if inst.target() == Some(BDOS) => arguments.extend(load.take()),
+            _ if writes(inst, &DE) || !falls_through(inst) => load = None,
+            _ => {},
+        }
+    }
+    arguments
+}
+
+// C と DE  (書き換えを調べるレジスタ)
+const C: [Operand; 2] = [Operand::Reg(Reg::C), Operand::RegPair(RegPair::BC)];
+const DE: [Operand; 3] = [Operand::Reg(Reg::D), Operand::Reg(Reg::E), Operand::RegPair(RegPair::DE)];
+
+// registers を書き換えるかもしれない命令か？  (CALL の先で書き換えることもある)
+fn writes(inst: &Instruction, registers: &[Operand]) -> bool {
+    use Mnemonic::*;
+    let c = |o: Option<&Operand>| o.is_some_and(|o| registers.contains(o));
+    match inst.mnemonic {
+        Exx | Call | Rst | Ldi | Ldir | Ldd | Lddr | Cpi | Cpir | Cpd | Cpdr => true,
+        Push | Cp | Bit | Out  => false,
+        Set | Res | Rlc | Rrc | Rl | Rr | Sla | Sra | Sll | Srl
+                               => c(inst.operands.last()),     // RLC (IX+d),C も C に書く
+        _                      => c(inst.operands.first()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Disassemble;
+
+    const PROGRAM: [u8; 21] = [
+        0x0e, 0x09,             // 0100  LD    C,09H
+        0x11, 0x20, 0x01,       // 0102  LD    DE,0120H
+        0xcd, 0x05, 0x00,       // 0105  CALL  0005H        C_WRITESTR  DE は文字列
+        0x0e, 0x02,             // 0108  LD    C,02H
+        0x5f,                   // 010A  LD    E,A          DE を書き換える
+        0xcd, 0x05, 0x00,       // 010B  CALL  0005H        C_WRITE
+        0x11, 0x00, 0x00,       // 010E  LD    DE,0000H
+        0x0c,                   // 0111  INC   C            機能番号が分からなくなる
+        0xc3, 0x05, 0x00,       // 0112  JP    0005H
+    ];
+
+    fn lines() -> Vec<Line> {
+        let mut dasm = Disassemble::new(PROGRAM.to_vec(), TPA.into());
+        dasm.disassemble_all();
+        dasm.result().to_vec()
+    }
+
+    #[test]
+    fn comments() {
+        let comments: Vec<(u16, String)> = bdos_comments(&lines()).into_iter().collect();
+        assert_eq!(comments, [(0x0105, String::from("C_WRITESTR")),
+                              (0x010b, String::from("C_WRITE")),
+                              (0x0112, String::from("BDOS (function unknown)"))]);
+    }
+
+    // BDOS に渡すまで DE が変わらない LD DE,nn だけ
+    #[test]
+    fn arguments() {
+        assert_eq!(bdos_arguments(&lines()).into_iter().collect::<Vec<_>>(), [0x0102, 0x010e]);
+    }
+}
@@ -0,0 +1,118 @@
+/*   Z80 Disassembler
+     name is system/mod.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+// **********************************************
+//      機種ごとの決まりごと
+//      ORG のアドレス、決まっているアドレスの名前、システムコールの注釈、
+//      ファイルのヘッダから分かる範囲とエントリーポイント
+// **********************************************
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::control::Control;
+use crate::label::SymbolKind;
+use crate::line::Line;
+use crate::loader::Image;
+
+pub mod cpm;
+pub mod msx;
+
+// **********************************************
+//      機種 (OS)
+// **********************************************
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum System {
+    Cpm,            // CP/M の .COM  0100H から
+    Msx,            // MSX  BIOS とシステム変数の名前、ROM カートリッジのヘッダ
+}
+
+impl System {
+    pub fn from_name(name: &str) -> Option<System> {
+        match name.to_ascii_lowercase().as_str() {
+            "cpm" | "com" => Some(System::Cpm),
+            "msx"         => Some(System::Msx),
+            _             => None,
+        }
+    }
+
+    // 拡張子から機種を決める
+    pub fn detect(filename: &str) -> Option<System> {
+        let extension = filename.rsplit_once('.').map(|(_, e)| e).unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "com"                 => Some(System::Cpm),
+            "rom" | "mx1" | "mx2" => Some(System::Msx),
+            _                     => None,
+        }
+    }
+
+    // 拡張子で決めた機種が、読み込んだ中身と合うか？  (MSX は ROM のヘッダがあること)
+    pub fn matches(self, image: &Image) -> bool {
+        match self {
+            System::Cpm => true,
+            System::Msx => msx::rom_header(image).is_some(),
+        }
+    }
+
+    // 読み込まれるアドレス (決まっている時だけ)
+    pub fn org(self) -> Option<u16> {
+        match self {
+            System::Cpm => Some(cpm::TPA),
+            System::Msx => None,
+        }
+    }
+
+    // 決まっているアドレスの名前
+    pub fn symbols(self) -> &'static [(u16, &'static str, SymbolKind)] {
+        match self {
+            System::Cpm => cpm::SYMBOLS,
+            System::Msx => msx::SYMBOLS,
+        }
+    }
+
+    // 行につける注釈  アドレス -> 注釈
+    pub fn comments(self, lines: &[Line]) -> BTreeMap<u16, String> {
+        match self {
+            System::Cpm => cpm::bdos_comments(lines),
+            System::Msx => BTreeMap::new(),
+        }
+    }
+
+    // nn を名前にする LD DE,nn のアドレス (システムコールに渡すもの)
+    pub fn arguments(self, lines: &[Line]) -> BTreeSet<u16> {
+        match self {
+            System::Cpm => cpm::bdos_arguments(lines),
+            System::Msx => BTreeSet::new(),
+        }
+    }
+
+    // 読み込んだイメージのヘッダから分かること (データの範囲、ラベル、エントリーポイント)
+    pub fn control(self, image: &Image) -> Control {
+        match self {
+            System::Cpm => Control::default(),
+            System::Msx => msx::rom_control(image),
+        }
+    }
+}
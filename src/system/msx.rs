@@ -0,0 +1,127 @@
+/*   Z80 Disassembler
+     name is system/msx.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use crate::control::{Control, Region, RegionKind};
+use crate::label::SymbolKind;
+use crate::label::SymbolKind::{Code, Data};
+use crate::loader::rom::HEADER_SIZE;
+use crate::loader::Image;
+
+// **********************************************
+//      MSX
+//      BIOS のジャンプテーブル (0000H-015FH) とシステム変数の名前
+//      名前の . は _ にする (H.KEYI -> H_KEYI)
+// **********************************************
+pub const SYMBOLS: &[(u16, &str, SymbolKind)] = &[
+    // ----- BIOS -----
+    (0x0000, "CHKRAM", Code), (0x0004, "CGTABL", Data), (0x0006, "VDP_DR", Data), (0x0007, "VDP_DW", Data),
+    (0x0008, "SYNCHR", Code), (0x000c, "RDSLT",  Code), (0x0010, "CHRGTR", Code), (0x0014, "WRSLT",  Code),
+    (0x0018, "OUTDO",  Code), (0x001c, "CALSLT", Code), (0x0020, "DCOMPR", Code), (0x0024, "ENASLT", Code),
+    (0x0028, "GETYPR", Code), (0x0030, "CALLF",  Code), (0x0038, "KEYINT", Code), (0x003b, "INITIO", Code),
+    (0x003e, "INIFNK", Code), (0x0041, "DISSCR", Code), (0x0044, "ENASCR", Code), (0x0047, "WRTVDP", Code),
+    (0x004a, "RDVRM",  Code), (0x004d, "WRTVRM", Code), (0x0050, "SETRD",  Code), (0x0053, "SETWRT", Code),
+    (0x0056, "FILVRM", Code), (0x0059, "LDIRMV", Code), (0x005c, "LDIRVM", Code), (0x005f, "CHGMOD", Code),
+    (0x0062, "CHGCLR", Code), (0x0066, "NMI",    Code), (0x0069, "CLRSPR", Code), (0x006c, "INITXT", Code),
+    (0x006f, "INIT32", Code), (0x0072, "INIGRP", Code), (0x0075, "INIMLT", Code), (0x0078, "SETTXT", Code),
+    (0x007b, "SETT32", Code), (0x007e, "SETGRP", Code), (0x0081, "SETMLT", Code), (0x0084, "CALPAT", Code),
+    (0x0087, "CALATR", Code), (0x008a, "GSPSIZ", Code), (0x008d, "GRPPRT", Code), (0x0090, "GICINI", Code),
+    (0x0093, "WRTPSG", Code), (0x0096, "RDPSG",  Code), (0x0099, "STRTMS", Code), (0x009c, "CHSNS",  Code),
+    (0x009f, "CHGET",  Code), (0x00a2, "CHPUT",  Code), (0x00a5, "LPTOUT", Code), (0x00a8, "LPTSTT", Code),
+    (0x00ab, "CNVCHR", Code), (0x00ae, "PINLIN", Code), (0x00b1, "INLIN",  Code), (0x00b4, "QINLIN", Code),
+    (0x00b7, "BREAKX", Code), (0x00ba, "ISCNTC", Code), (0x00bd, "CKCNTC", Code), (0x00c0, "BEEP",   Code),
+    (0x00c3, "CLS",    Code), (0x00c6, "POSIT",  Code), (0x00c9, "FNKSB",  Code), (0x00cc, "ERAFNK", Code),
+    (0x00cf, "DSPFNK", Code), (0x00d2, "TOTEXT", Code), (0x00d5, "GTSTCK", Code), (0x00d8, "GTTRIG", Code),
+    (0x00db, "GTPAD",  Code), (0x00de, "GTPDL",  Code), (0x00e1, "TAPION", Code), (0x00e4, "TAPIN",  Code),
+    (0x00e7, "TAPIOF", Code), (0x00ea, "TAPOON", Code), (0x00ed, "TAPOUT", Code), (0x00f0, "TAPOOF", Code),
+    (0x00f3, "STMOTR", Code), (0x00f6, "LFTQ",   Code), (0x00f9, "PUTQ",   Code), (0x00fc, "RIGHTC", Code),
+    (0x00ff, "LEFTC",  Code), (0x0102, "UPC",    Code), (0x0105, "TUPC",   Code), (0x0108, "DOWNC",  Code),
+    (0x010b, "TDOWNC", Code), (0x010e, "SCALXY", Code), (0x0111, "MAPXYC", Code), (0x0114, "FETCHC", Code),
+    (0x0117, "STOREC", Code), (0x011a, "SETATR", Code), (0x011d, "READC",  Code), (0x0120, "SETC",   Code),
+    (0x0123, "NSETCX", Code), (0x0126, "GTASPC", Code), (0x0129, "PNTINI", Code), (0x012c, "SCANR",  Code),
+    (0x012f, "SCANL",  Code), (0x0132, "CHGCAP", Code), (0x0135, "CHGSND", Code), (0x0138, "RSLREG", Code),
+    (0x013b, "WSLREG", Code), (0x013e, "RDVDP",  Code), (0x0141, "SNSMAT", Code), (0x0144, "PHYDIO", Code),
+    (0x0147, "FORMAT", Code), (0x014a, "ISFLIO", Code), (0x014d, "OUTDLP", Code), (0x0150, "GETVCP", Code),
+    (0x0153, "GETVC2", Code), (0x0156, "KILBUF", Code), (0x0159, "CALBAS", Code),
+    (0x015c, "SUBROM", Code), (0x015f, "EXTROM", Code),     // MSX2 から
+
+    // ----- システム変数 -----
+    (0xf380, "RDPRIM", Code), (0xf385, "WRPRIM", Code), (0xf38c, "CLPRIM", Code), (0xf39a, "USRTAB", Data),
+    (0xf3ae, "LINL40", Data), (0xf3af, "LINL32", Data), (0xf3b0, "LINLEN", Data), (0xf3b1, "CRTCNT", Data),
+    (0xf3b2, "CLMLST", Data), (0xf3b3, "TXTNAM", Data), (0xf3b5, "TXTCOL", Data), (0xf3b7, "TXTCGP", Data),
+    (0xf3b9, "TXTATR", Data), (0xf3bb, "TXTPAT", Data), (0xf3bd, "T32NAM", Data), (0xf3bf, "T32COL", Data),
+    (0xf3c1, "T32CGP", Data), (0xf3c3, "T32ATR", Data), (0xf3c5, "T32PAT", Data), (0xf3c7, "GRPNAM", Data),
+    (0xf3c9, "GRPCOL", Data), (0xf3cb, "GRPCGP", Data), (0xf3cd, "GRPATR", Data), (0xf3cf, "GRPPAT", Data),
+    (0xf3d1, "MLTNAM", Data), (0xf3d3, "MLTCOL", Data), (0xf3d5, "MLTCGP", Data), (0xf3d7, "MLTATR", Data),
+    (0xf3d9, "MLTPAT", Data), (0xf3db, "CLIKSW", Data), (0xf3dc, "CSRY",   Data), (0xf3dd, "CSRX",   Data),
+    (0xf3de, "CNSDFG", Data), (0xf3df, "RG0SAV", Data), (0xf3e0, "RG1SAV", Data), (0xf3e1, "RG2SAV", Data),
+    (0xf3e2, "RG3SAV", Data), (0xf3e3, "RG4SAV", Data), (0xf3e4, "RG5SAV", Data), (0xf3e5, "RG6SAV", Data),
+    (0xf3e6, "RG7SAV", Data), (0xf3e7, "STATFL", Data), (0xf3e8, "TRGFLG", Data), (0xf3e9, "FORCLR", Data),
+    (0xf3ea, "BAKCLR", Data), (0xf3eb, "BDRCLR", Data), (0xf3f3, "QUEUES", Data), (0xf3f5, "FRCNEW", Data),
+    (0xf3f6, "SCNCNT", Data), (0xf3f7, "REPCNT", Data), (0xf3f8, "PUTPNT", Data), (0xf3fa, "GETPNT", Data),
+    (0xf416, "PRTFLG", Data), (0xf41f, "KBUF",   Data), (0xf55e, "BUF",    Data), (0xf672, "MEMSIZ", Data),
+    (0xf674, "STKTOP", Data), (0xf676, "TXTTAB", Data), (0xf69b, "FRETOP", Data), (0xf6c2, "VARTAB", Data),
+    (0xf6c4, "ARYTAB", Data), (0xf6c6, "STREND", Data), (0xf7f6, "DAC",    Data), (0xf847, "ARG",    Data),
+    (0xf87f, "FNKSTR", Data), (0xfbd9, "CLIKFL", Data), (0xfbda, "OLDKEY", Data), (0xfbe5, "NEWKEY", Data),
+    (0xfbf0, "KEYBUF", Data), (0xfc18, "LINWRK", Data), (0xfc40, "PATWRK", Data), (0xfc48, "BOTTOM", Data),
+    (0xfc4a, "HIMEM",  Data), (0xfc9e, "JIFFY",  Data), (0xfca2, "INTCNT", Data), (0xfca9, "CSRSW",  Data),
+    (0xfcab, "CAPST",  Data), (0xfcac, "KANAST", Data), (0xfcaf, "SCRMOD", Data), (0xfcb0, "OLDSCR", Data),
+    (0xfcc1, "EXPTBL", Data), (0xfcc5, "SLTTBL", Data), (0xfcc9, "SLTATR", Data), (0xfd09, "SLTWRK", Data),
+    (0xfd89, "PROCNM", Data), (0xfd99, "DEVICE", Data), (0xfd9a, "H_KEYI", Code), (0xfd9f, "H_TIMI", Code),
+];
+
+// ROM カートリッジのヘッダのアドレス (4000H か 8000H の "AB")
+pub fn rom_header(image: &Image) -> Option<u16> {
+    [0x4000, 0x8000].into_iter().find(|a| image.byte(*a) == Some(b'A') && image.byte(a + 1) == Some(b'B'))
+}
+
+// **********************************************
+//      ROM カートリッジのヘッダ
+//      ヘッダはデータにして、INIT / STATEMENT / DEVICE の飛び先をエントリーポイントにする
+//      TEXT は BASIC のプログラムを指しているので、ラベルだけつける
+// **********************************************
+pub fn rom_control(image: &Image) -> Control {
+    let mut control = Control::default();
+    let Some(header) = rom_header(image) else {
+        return control;
+    };
+    control.regions.push(Region{ start: header, end: header + 1, kind: RegionKind::Text });
+    control.labels.push((header, String::from("ROM_HEADER")));
+    for (i, name) in ["INIT", "STATEMENT", "DEVICE", "TEXT"].iter().enumerate() {
+        let address = header + 2 + i as u16 * 2;
+        let pointer = image.word(address).unwrap_or(0);
+        let kind = if pointer == 0 { RegionKind::Words } else { RegionKind::Pointers };
+        control.regions.push(Region{ start: address, end: address + 1, kind });
+        if pointer == 0 {
+            continue;
+        }
+        control.labels.push((pointer, format!("ROM_{}", name)));
+        if *name != "TEXT" {
+            control.entries.push(pointer);
+        }
+    }
+    control.regions.push(Region{ start: header + 10, end: header + HEADER_SIZE - 1, kind: RegionKind::Bytes });
+    control
+}
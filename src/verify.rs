@@ -0,0 +1,215 @@
+/*   Z80 Disassembler
+     name is verify.rs
+
+ Copyright (c) 2023 Windy
+ Released under the MIT license
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in
+all copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+THE SOFTWARE.
+
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::assemble::assemble;
+use crate::encode::encode;
+use crate::format::{format_instruction, Syntax};
+use crate::label::Labels;
+use crate::line::Line;
+use crate::source::{bytes_line, source_lines, Dialect};
+
+// **********************************************
+//      元のファイルと食い違ったところ
+// **********************************************
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub address: u16,       // 行のアドレス
+    pub expected: Vec<u8>,  // 元のファイルのバイト列
+    pub actual: Vec<u8>,    // アセンブルし直したバイト列
+    pub text: String,       // ソースの行 (アセンブルできなかった時は理由も)
+}
+
+// **********************************************
+//      逆アセンブル結果をアセンブルし直して、元のファイルと比べる
+//      bin_data は org に置かれたファイルの中身
+//      -a と同じソースを作って、アセンブラで読み直す
+//      (ソースの書き方と、命令の表と逆アセンブラの食い違いをまとめて確かめる)
+//      アセンブルできない行は、理由を出して DB に書き換えてから続ける
+// **********************************************
+pub fn verify(lines: &[Line], bin_data: &[u8], org: u16, labels: Option<&Labels>, dialect: Dialect) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let expected = |line: &Line| {
+        let start = line.address().wrapping_sub(org) as usize;
+        bin_data.get(start..start + line.size()).unwrap_or_default()
+    };
+
+    // ----- 行が隙間なく並んでいるか --------
+    let mut offset = 0;
+    for line in lines {
+        let start = line.address().wrapping_sub(org) as usize;
+        if start != offset {
+            differences.push(Difference{ address: org.wrapping_add(offset as u16),
+                                         expected: Vec::new(),
+                                         actual: Vec::new(),
+                                         text: format!("line starts at {:04X}", line.address()) });
+        }
+        offset = start + line.size();
+    }
+    if offset < bin_data.len() {
+        differences.push(Difference{ address: org.wrapping_add(offset as u16),
+                                     expected: Vec::new(),
+                                     actual: Vec::new(),
+                                     text: format!("{} byte(s) not disassembled", bin_data.len() - offset) });
+    }
+
+    // ----- 公式の命令は、命令の表で同じバイト列に戻るはず --------
+    // (戻らない命令はソースでは DB になるので、ここで見つける)
+    let mut failed = BTreeSet::new();       // 食い違いを見つけた lines の番号
+    for (i, line) in lines.iter().enumerate() {
+        let Line::Code(inst) = line else {
+            continue;
+        };
+        if inst.undocumented {
+            continue;
+        }
+        let text = format_instruction(inst, None, Syntax::Source);
+        let (actual, text) = match encode(inst) {
+            Ok(bytes) if bytes == inst.bytes => continue,
+            Ok(bytes) => (bytes, text),
+            Err(e)    => (Vec::new(), format!("{}  ({})", text, e)),
+        };
+        differences.push(Difference{ address: inst.address, expected: inst.bytes.clone(), actual, text });
+        failed.insert(i);
+    }
+
+    // ----- アセンブルする --------
+    let mut source = source_lines(org, lines, labels, &BTreeMap::new(), dialect);
+    let program = loop {
+        let text: Vec<&str> = source.iter().map(|(_, t)| t.as_str()).collect();
+        let error = match assemble(&text.join("\n")) {
+            Ok(program) => break program,
+            Err(error) => error,
+        };
+        let position = error.line - 1;
+        let (index, text) = source[position].clone();
+        let message = format!("{}  ({})", text.trim(), error.message);
+        let Some(line) = index.map(|i| &lines[i]) else {       // ORG や EQU の行
+            differences.push(Difference{ address: org, expected: Vec::new(), actual: Vec::new(), text: message });
+            return differences;
+        };
+        let db = bytes_line(expected(line), dialect);
+        differences.push(Difference{ address: line.address(), expected: expected(line).to_vec(), actual: Vec::new(), text: message });
+        if text == db {         // DB にしてもアセンブルできない
+            return differences;
+        }
+        failed.extend(index);
+
+        // 命令の行は元のバイト列の DB にして、ラベルなどの行は取る
+        if source.iter().rposition(|(n, _)| *n == index) == Some(position) {
+            source[position].1 = db;
+        } else {
+            source.remove(position);
+        }
+    };
+
+    // ----- 元のファイルと比べる --------
+    for (i, line) in lines.iter().enumerate() {
+        if matches!(line, Line::Skip { .. }) || failed.contains(&i) {
+            continue;
+        }
+        let start = line.address().wrapping_sub(program.origin) as usize;
+        let actual = program.bytes.get(start..start + line.size()).unwrap_or_default();
+        if actual != expected(line) {
+            let text = source.iter().rfind(|(n, _)| *n == Some(i)).map(|(_, t)| t.trim().to_string()).unwrap_or_default();
+            differences.push(Difference{ address: line.address(), expected: expected(line).to_vec(), actual: actual.to_vec(), text });
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble_one, Disassemble};
+
+    const ORG: u16 = 0x8000;
+    const DATA: [u8; 9] = [
+        0x21, 0x05, 0x80,       // 8000  LD    HL,8005H
+        0xdd, 0x94,             // 8003  SUB   IXH          未定義命令
+        0xfd, 0xcb, 0x05, 0x00, // 8005  RLC   (IY+5D),B    未定義命令
+    ];
+
+    fn disassemble() -> Disassemble {
+        let mut dasm = Disassemble::new(DATA.to_vec(), ORG.into());
+        dasm.disassemble_all();
+        dasm.make_labels("L");
+        dasm
+    }
+
+    #[test]
+    fn same_binary() {
+        let dasm = disassemble();
+        for dialect in [Dialect::Sjasmplus, Dialect::Pasmo, Dialect::Z80asm, Dialect::M80] {
+            assert_eq!(dasm.verify(dialect), Vec::new(), "{:?}", dialect);
+        }
+    }
+
+    // 元のファイルを１バイト壊すと、その行が見つかる (未定義命令も)
+    #[test]
+    fn corrupted_byte() {
+        let dasm = disassemble();
+        let labels = dasm.labels();
+        for (offset, address, actual) in [(1, 0x8000, vec![0x21, 0x05, 0x80]),
+                                          (4, 0x8003, vec![0xdd, 0x94]),
+                                          (8, 0x8005, vec![0xfd, 0xcb, 0x05, 0x00])] {
+            let mut data = DATA.to_vec();
+            data[offset] ^= 0x01;
+            let differences = verify(dasm.result(), &data, ORG, labels, Dialect::Sjasmplus);
+            assert_eq!(differences.len(), 1, "{:?}", differences);
+            assert_eq!(differences[0].address, address);
+            assert_eq!(differences[0].expected, data[(address - ORG) as usize..][..actual.len()]);
+            assert_eq!(differences[0].actual, actual);
+        }
+    }
+
+    // 逆アセンブラが命令を読み違えると、命令の表で戻したバイト列が食い違う
+    #[test]
+    fn wrong_decode() {
+        let mut inst = disassemble_one(&[0x09], ORG).unwrap();      // ADD   HL,BC
+        inst.bytes = vec![0x39];                                    // 本当は ADD   HL,SP
+        let differences = verify(&[Line::Code(inst)], &[0x39], ORG, None, Dialect::Sjasmplus);
+        assert_eq!(differences.len(), 1, "{:?}", differences);
+        assert_eq!((differences[0].expected.clone(), differences[0].actual.clone()), (vec![0x39], vec![0x09]));
+    }
+
+    // 逆アセンブルしていないところがある
+    #[test]
+    fn missing_lines() {
+        let dasm = disassemble();
+        let lines = &dasm.result()[1..];
+        let differences = verify(lines, &DATA, ORG, None, Dialect::Sjasmplus);
+        assert_eq!(differences[0].address, ORG);
+        assert_eq!(differences[0].text, "line starts at 8003");
+
+        let mut data = DATA.to_vec();
+        data.push(0x00);
+        let differences = verify(dasm.result(), &data, ORG, None, Dialect::Sjasmplus);
+        assert_eq!(differences.len(), 1, "{:?}", differences);
+        assert_eq!(differences[0].text, "1 byte(s) not disassembled");
+    }
+}